@@ -1,29 +1,81 @@
 #[cfg(not(feature = "library"))]
 use cosmwasm_std::entry_point;
-use cosmwasm_std::{to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Addr, BankMsg, Decimal};
-use cw2::set_contract_version;
+use cosmwasm_std::{attr, to_binary, from_binary, Attribute, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, StdError, Addr, BankMsg, Order, Timestamp};
+use cw2::{get_contract_version, set_contract_version};
 use cosmwasm_std::WasmMsg::Execute as MsgExecuteContract;
 
 use crate::error::ContractError;
+use crate::payout;
 use crate::msg::{
     ExecuteMsg, 
     GetMetadataResponse, 
     InstantiateMsg, 
+    MigrateMsg,
     QueryMsg, 
     Tmessage, 
     SendTokenMsg, 
     Royalties,
     OwnerOf,
+    ApprovedForAll,
+    NftInfo,
     Creator,
+    GetListedResponse,
+    ListedCursor,
+    PriceOrder,
+    NFT,
+    Cw721ReceiveMsg,
+    ReceiveNftMsg,
+    OffersResponse,
+    CollectionOffersResponse,
+    AuctionsResponse,
+    Decay,
+    DutchPrice,
+    DutchPricesResponse,
+    Denom,
+    Cw20ReceiveMsg,
+    Cw20HookMsg,
+    FeeRecipient,
+    SimulateBuyResponse,
+    GetSalesResponse,
+    GetStatsResponse,
+    FloorResponse,
+    FloorPrice,
+    ListingResponse,
+    ListItem,
+    RolesResponse,
+    Payment,
+    SwapItem,
+    SwapsResponse,
 };
-use crate::state::{State, STATE, Token};
-use std::collections::HashMap;
+use crate::state::{
+    State, STATE, Config, CONFIG, load_config, Ownership, OWNERSHIP, load_ownership, METADATA_EDITOR, load_metadata_editor, MODERATORS, Token, ListingKind, Offer, CollectionOffer, Auction, Bid, LISTING_COUNT, COLLECTION_OFFER_SEQ, AUCTIONS,
+    Swap, SWAP_SEQ, swaps, listings, offers, collection_offers, add_listing, remove_listing, migrate_legacy_listings, LEGACY_STATE, LEGACY_LISTED, Sale, SaleStats, sales, STATS, record_sale, LISTED_DENOMS,
+};
+use cw_storage_plus::Bound;
+use cw_utils::Expiration;
 
+use semver::Version;
 use serde::{Deserialize, Serialize};
 
 // version info for migration info
 const CONTRACT_NAME: &str = "Nebula Exchange";
-const CONTRACT_VERSION: &str = "0.0.1";
+const CONTRACT_VERSION: &str = "0.1.0";
+
+// platform fee can never be set above 10%
+const MAX_FEE_BASIS_POINTS: u16 = 1_000;
+
+// royalty and platform fee together can never take the whole price
+const MAX_ROYALTY_BASIS_POINTS: u16 = 10_000 - MAX_FEE_BASIS_POINTS;
+
+// pagination defaults for listing queries
+const DEFAULT_LIMIT: u32 = 30;
+const MAX_LIMIT: u32 = 100;
+
+// most tokens a single batch message can touch
+const MAX_BATCH_SIZE: u32 = 100;
+
+// a bid this close to the end of an auction pushes the end back to this far out
+const AUCTION_EXTENSION_SECONDS: u64 = 300;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct GetOwnerResponse {
@@ -33,12 +85,13 @@ pub struct GetOwnerResponse {
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
 pub struct Approval {
-    spender: String
+    pub spender: String,
+    pub expires: Expiration,
 }
 
-fn check_duplicate(list: &[Token], target_id: &String) -> Option<usize> {
-    let id_index_map: HashMap<_, _> = list.iter().enumerate().map(|(i, item)| (item.id.clone(), i)).collect();
-    id_index_map.get(target_id).cloned()
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ApprovedForAllResponse {
+    pub operators: Vec<Approval>,
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
@@ -48,9 +101,27 @@ pub fn instantiate(
     _info: MessageInfo,
     msg: InstantiateMsg,
 ) -> Result<Response, ContractError> {
+    let mut config = Config::default();
+    if let Some(fee_basis_points) = msg.fee_basis_points {
+        config.fee_basis_points = fee_basis_points;
+    }
+    if let Some(fee_recipients) = msg.fee_recipients {
+        config.fee_recipients = fee_recipients;
+    }
+    if let Some(max_royalty_basis_points) = msg.max_royalty_basis_points {
+        config.max_royalty_basis_points = max_royalty_basis_points;
+    }
+    let config = execute::validate_config(deps.as_ref(), config)?;
+    let royalties = execute::validate_royalties(deps.as_ref(), Royalties {
+        seller_fee_basis_points: msg.basis_points,
+        creators: msg.creators
+    }, &config)?;
+
     let contractAddress = msg.contract;
     let state = State {
         flagged: false,
+        flag_reason: None,
+        flag_time: None,
         collection: msg.collection,
         contract: contractAddress.clone(),
         symbol: msg.symbol,
@@ -58,26 +129,122 @@ pub fn instantiate(
         logo_uri: msg.logo_uri,
         banner_uri: msg.banner_uri,
         supply: msg.supply,
-        royalties: Royalties {
-            seller_fee_basis_points: msg.basis_points,
-            creators: msg.creators
-        },
+        royalties,
         owner: deps.querier.query_wasm_contract_info(contractAddress).unwrap().creator, 
-        listed: vec![],
         contact: "".to_string(), // updated later
         discord: "".to_string(),
         twitter: "".to_string(),
         telegram: "".to_string(),
         website: "".to_string()
     };
+
     set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
     STATE.save(deps.storage, &state)?;
+    CONFIG.save(deps.storage, &config)?;
+    OWNERSHIP.save(deps.storage, &Ownership {
+        admin: Some(state.owner.clone()),
+        pending_admin: None,
+        pending_expiry: None,
+    })?;
+    METADATA_EDITOR.save(deps.storage, &state.owner)?;
 
     Ok(Response::new()
-        .add_attribute("method", "instantiate")
+        .add_attribute("action", "instantiate")
+        .add_attribute("contract", state.contract)
     )
 }
 
+#[cfg_attr(not(feature = "library"), entry_point)]
+pub fn migrate(mut deps: DepsMut, _env: Env, msg: MigrateMsg) -> Result<Response, ContractError> {
+    let stored = get_contract_version(deps.storage)?;
+    if stored.contract != CONTRACT_NAME {
+        return Err(ContractError::WrongContract { name: stored.contract });
+    }
+    let from: Version = stored.version.parse()?;
+    let to: Version = CONTRACT_VERSION.parse()?;
+    if from > to {
+        return Err(ContractError::CannotDowngrade { from: stored.version, to: CONTRACT_VERSION.to_string() });
+    }
+
+    if from < Version::new(0, 1, 0) {
+        migrate_legacy_state(deps.branch(), msg)?;
+    }
+    set_contract_version(deps.storage, CONTRACT_NAME, CONTRACT_VERSION)?;
+
+    // only the first batch of legacy listings moves here, `MigrateListings` moves the rest
+    let (migrated, remaining) = migrate_legacy_listings(deps.storage, MAX_BATCH_SIZE as usize)?;
+    Ok(Response::new()
+        .add_attribute("action", "migrate")
+        .add_attribute("from_version", stored.version)
+        .add_attribute("to_version", CONTRACT_VERSION)
+        .add_attributes(migrated_listing_attributes(&migrated, remaining))
+    )
+}
+
+/// Migrated listings are announced like new ones, so the order book can still be rebuilt from events
+fn migrated_listing_attributes(migrated: &[Token], remaining: usize) -> Vec<Attribute> {
+    let mut attrs = vec![
+        attr("migrated_listings", migrated.len().to_string()),
+        attr("remaining_listings", remaining.to_string()),
+        attr("token_ids", migrated.iter().map(|t| t.id.as_str()).collect::<Vec<&str>>().join(",")),
+    ];
+    attrs.extend(migrated.iter().flat_map(execute::listing_attributes));
+    attrs
+}
+
+/// Rewrites a 0.0.1 state: creator shares in whole percents adding up to 100 become basis points
+/// adding up to 10000, checked like any other royalty. Creators and basis points given in `msg`
+/// replace the old ones. The listings are queued in `LEGACY_LISTED` to be moved in batches.
+fn migrate_legacy_state(deps: DepsMut, msg: MigrateMsg) -> Result<(), ContractError> {
+    let legacy = LEGACY_STATE.load(deps.storage)?;
+
+    let creators = match msg.creators {
+        Some(creators) => creators,
+        None => legacy.royalties.creators
+            .into_iter()
+            .map(|c| match u16::try_from(c.share) {
+                Ok(share) => Ok(Creator { address: c.address, share: share * 100 }),
+                Err(_) => Err(ContractError::InvalidCreatorShares {}),
+            })
+            .collect::<Result<Vec<Creator>, ContractError>>()?,
+    };
+    let seller_fee_basis_points = msg.basis_points.map_or(legacy.royalties.seller_fee_basis_points, u32::from);
+    let royalties = Royalties { seller_fee_basis_points, creators };
+    if royalties.seller_fee_basis_points > MAX_ROYALTY_BASIS_POINTS as u32 {
+        return Err(ContractError::RoyaltyTooHigh { max: MAX_ROYALTY_BASIS_POINTS });
+    }
+    // 0.0.1 had no royalty cap, so raise the configured one rather than cut an existing royalty
+    let mut config = load_config(deps.storage)?;
+    if royalties.seller_fee_basis_points > config.max_royalty_basis_points as u32 {
+        config.max_royalty_basis_points = royalties.seller_fee_basis_points as u16;
+        CONFIG.save(deps.storage, &config)?;
+    }
+    let royalties = execute::validate_royalties(deps.as_ref(), royalties, &config)?;
+
+    STATE.save(deps.storage, &State {
+        flagged: legacy.flagged,
+        flag_reason: None,
+        flag_time: None,
+        collection: legacy.collection,
+        contract: legacy.contract,
+        description: legacy.description,
+        symbol: legacy.symbol,
+        logo_uri: legacy.logo_uri,
+        banner_uri: legacy.banner_uri,
+        supply: legacy.supply,
+        owner: legacy.owner,
+        royalties,
+        contact: legacy.contact,
+        discord: legacy.discord,
+        twitter: legacy.twitter,
+        telegram: legacy.telegram,
+        website: legacy.website,
+    })?;
+    let listed: Vec<Token> = legacy.listed.into_iter().filter(|t| t.is_listed).collect();
+    LEGACY_LISTED.save(deps.storage, &listed)?;
+    Ok(())
+}
+
 #[cfg_attr(not(feature = "library"), entry_point)]
 pub fn execute(
     deps: DepsMut,
@@ -86,10 +253,43 @@ pub fn execute(
     msg: ExecuteMsg,
 ) -> Result<Response, ContractError> {
     match msg {
-        ExecuteMsg::List { id, price, expires } => execute::list(deps, id, price, expires, info.sender),
+        ExecuteMsg::List { id, price, expires, denom, reserved_for } => execute::list(deps, id, price, expires, ListingKind::Fixed, denom, reserved_for, info.sender, env),
+        ExecuteMsg::ListDutch { id, price, dutch, expires, denom } => execute::list(deps, id, price, expires, ListingKind::Dutch(dutch), denom, None, info.sender, env),
+        ExecuteMsg::BatchList { items } => execute::batch_list(deps, items, info.sender, env),
+        ExecuteMsg::UpdatePrice { id, price, expires } => execute::update_price(deps, id, price, expires, info.sender, env),
         ExecuteMsg::Buy { id } => execute::buy(deps, id, &info, env),
+        ExecuteMsg::Sweep { max_count, max_total_price, ids } => execute::sweep(deps, max_count, max_total_price, ids, &info, env),
         ExecuteMsg::DeList { id } => execute::delist(deps, id, &info, env),
-        ExecuteMsg::Flag { enabled } => execute::flag(enabled, deps),
+        ExecuteMsg::BatchDeList { ids } => execute::batch_delist(deps, ids, info.sender),
+        ExecuteMsg::PurgeExpired { limit } => execute::purge_expired(deps, limit, env),
+        ExecuteMsg::InvalidateListing { id } => execute::invalidate_listing(deps, id, env),
+        ExecuteMsg::MigrateListings { limit } => execute::migrate_listings(deps, limit),
+        ExecuteMsg::ReceiveNft(msg) => execute::receive_nft(deps, msg, &info, env),
+        ExecuteMsg::Receive(msg) => execute::receive(deps, msg, &info, env),
+        ExecuteMsg::MakeOffer { id, expires } => execute::make_offer(deps, id, expires, &info, env),
+        ExecuteMsg::CancelOffer { id } => execute::cancel_offer(deps, id, &info),
+        ExecuteMsg::AcceptOffer { id, bidder } => execute::accept_offer(deps, id, bidder, &info, env),
+        ExecuteMsg::MakeCollectionOffer { price, quantity, expires } => execute::make_collection_offer(deps, price, quantity, expires, &info, env),
+        ExecuteMsg::CancelCollectionOffer { offer_id } => execute::cancel_collection_offer(deps, offer_id, &info),
+        ExecuteMsg::AcceptCollectionOffer { offer_id, token_id } => execute::accept_collection_offer(deps, offer_id, token_id, &info, env),
+        ExecuteMsg::PlaceBid { id } => execute::place_bid(deps, id, &info, env),
+        ExecuteMsg::SettleAuction { id } => execute::settle_auction(deps, id, env),
+        ExecuteMsg::CancelAuction { id } => execute::cancel_auction(deps, id, &info),
+        ExecuteMsg::ProposeSwap { offered, requested, expires } => execute::propose_swap(deps, offered, requested, expires, &info, env),
+        ExecuteMsg::AcceptSwap { id } => execute::accept_swap(deps, id, &info, env),
+        ExecuteMsg::CancelSwap { id } => execute::cancel_swap(deps, id, &info),
+        ExecuteMsg::Flag { enabled, reason } => execute::flag(enabled, reason, deps, info.sender, env),
+        ExecuteMsg::ProposeAdmin { new_admin, expiry } => execute::propose_admin(deps, new_admin, expiry, info.sender, env),
+        ExecuteMsg::AcceptAdmin {} => execute::accept_admin(deps, info.sender, env),
+        ExecuteMsg::RenounceAdmin {} => execute::renounce_admin(deps, info.sender),
+        ExecuteMsg::SetMetadataEditor { editor } => execute::set_metadata_editor(deps, editor, info.sender),
+        ExecuteMsg::UpdateModerators { add, remove } => execute::update_moderators(deps, add, remove, info.sender),
+        ExecuteMsg::UpdateConfig {
+            accepted_denoms,
+            fee_basis_points,
+            fee_recipients,
+            max_royalty_basis_points,
+        } => execute::update_config(deps, accepted_denoms, fee_basis_points, fee_recipients, max_royalty_basis_points, info.sender),
         ExecuteMsg::UpdateMetadata {
             creators,
             description, 
@@ -133,13 +333,245 @@ pub mod execute {
         Bank(BankMsg)
     }
 
-    pub fn flag(enabled: bool, deps: DepsMut) -> Result<Response, ContractError> {
+    /// transfer_nft on the collection contract, executed by the exchange
+    pub fn send_token(contract: &str, recipient: String, token_id: String) -> StdResult<WasmMsg> {
+        Ok(MsgExecuteContract {
+            contract_addr: contract.into(),
+            msg: to_binary(&Tmessage { transfer_nft: SendTokenMsg { recipient, token_id } })?,
+            funds: vec![],
+        })
+    }
+
+    /// cw721 owner_of for a token in this exchange's collection
+    pub fn query_owner(deps: Deps, contract: &str, id: &str) -> StdResult<GetOwnerResponse> {
+        deps.querier.query_wasm_smart(
+            contract, 
+            &QueryWrapper { owner_of: OwnerOf { token_id: id.to_string() }}
+        )
+    }
+
+    /// Whether the exchange holds an unexpired approval for the token, or is an operator for its owner
+    fn is_approved(deps: Deps, env: &Env, contract: &str, owner: &GetOwnerResponse) -> StdResult<bool> {
+        let exchange = env.contract.address.as_str();
+        let approved = |a: &Approval| a.spender == exchange && !a.expires.is_expired(&env.block);
+        if owner.approvals.iter().any(approved) {
+            return Ok(true);
+        }
+
+        let mut start_after = None;
+        loop {
+            let resp: ApprovedForAllResponse = deps.querier.query_wasm_smart(
+                contract,
+                &ApprovedForAllWrapper { approved_for_all: ApprovedForAll { owner: owner.owner.clone(), start_after, limit: Some(MAX_LIMIT) } }
+            )?;
+            if resp.operators.iter().any(approved) {
+                return Ok(true);
+            }
+            match resp.operators.last() {
+                Some(last) => start_after = Some(last.spender.clone()),
+                None => return Ok(false),
+            }
+        }
+    }
+
+    /// Whether the seller still owns the token and the exchange can still move it
+    fn is_live(deps: Deps, env: &Env, s: &State, token: &Token) -> bool {
+        if token.escrowed {
+            return true;
+        }
+        // a burned token fails the query
+        match query_owner(deps, &s.contract, &token.id) {
+            Ok(resp) => resp.owner == token.owner && is_approved(deps, env, &s.contract, &resp).unwrap_or(false),
+            Err(_) => false,
+        }
+    }
+
+    /// Caps the platform fee and checks the fee recipients' shares add up to 100%
+    pub fn validate_config(deps: Deps, mut config: Config) -> Result<Config, ContractError> {
+        if config.fee_basis_points > MAX_FEE_BASIS_POINTS {
+            return Err(ContractError::FeeTooHigh { max: MAX_FEE_BASIS_POINTS });
+        }
+        if config.fee_recipients.is_empty()
+            || config.fee_recipients.iter().map(|r| r.share as u32).sum::<u32>() != 10_000
+        {
+            return Err(ContractError::InvalidFeeRecipients {});
+        }
+        if config.max_royalty_basis_points > MAX_ROYALTY_BASIS_POINTS {
+            return Err(ContractError::RoyaltyTooHigh { max: MAX_ROYALTY_BASIS_POINTS });
+        }
+        for recipient in config.fee_recipients.iter_mut() {
+            recipient.address = deps.api.addr_validate(&recipient.address)?.to_string();
+        }
+        Ok(config)
+    }
+
+    /// Royalty within the configured cap, split between valid creator addresses
+    pub fn validate_royalties(deps: Deps, mut royalties: Royalties, config: &Config) -> Result<Royalties, ContractError> {
+        if royalties.seller_fee_basis_points > config.max_royalty_basis_points as u32 {
+            return Err(ContractError::RoyaltyTooHigh { max: config.max_royalty_basis_points });
+        }
+        // without creators there is nobody to pay a royalty to
+        let total = royalties.creators.iter().map(|c| c.share as u32).sum::<u32>();
+        if (royalties.creators.is_empty() && royalties.seller_fee_basis_points > 0)
+            || (!royalties.creators.is_empty() && total != 10_000)
+        {
+            return Err(ContractError::InvalidCreatorShares {});
+        }
+        for creator in royalties.creators.iter_mut() {
+            creator.address = deps.api.addr_validate(&creator.address)?.to_string();
+        }
+        Ok(royalties)
+    }
+
+    pub fn update_config(
+                deps: DepsMut,
+                accepted_denoms: Option<Vec<String>>,
+                fee_basis_points: Option<u16>,
+                fee_recipients: Option<Vec<FeeRecipient>>,
+                max_royalty_basis_points: Option<u16>,
+                sender: Addr,
+    ) -> Result<Response, ContractError> {
+        assert_admin(deps.as_ref(), &sender)?;
+
+        let mut config = load_config(deps.storage)?;
+        if let Some(accepted_denoms) = accepted_denoms {
+            config.accepted_denoms = accepted_denoms;
+        }
+        if let Some(fee_basis_points) = fee_basis_points {
+            config.fee_basis_points = fee_basis_points;
+        }
+        if let Some(fee_recipients) = fee_recipients {
+            config.fee_recipients = fee_recipients;
+        }
+        if let Some(max_royalty_basis_points) = max_royalty_basis_points {
+            config.max_royalty_basis_points = max_royalty_basis_points;
+        }
+        let config = validate_config(deps.as_ref(), config)?;
+        CONFIG.save(deps.storage, &config)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "update_config")
+            .add_attribute("accepted_denoms", config.accepted_denoms.join(","))
+            .add_attribute("fee_basis_points", config.fee_basis_points.to_string())
+            .add_attribute("max_royalty_basis_points", config.max_royalty_basis_points.to_string())
+        )
+    }
+
+    fn assert_admin(deps: Deps, sender: &Addr) -> Result<(), ContractError> {
+        match load_ownership(deps.storage)?.admin {
+            Some(admin) if admin == sender.as_str() => Ok(()),
+            _ => Err(ContractError::Unauthorized {}),
+        }
+    }
+
+    pub fn propose_admin(deps: DepsMut, new_admin: String, expiry: Option<Expiration>, sender: Addr, env: Env) -> Result<Response, ContractError> {
+        assert_admin(deps.as_ref(), &sender)?;
+        let new_admin = deps.api.addr_validate(&new_admin)?;
+        if expiry.map_or(false, |e| e.is_expired(&env.block)) {
+            return Err(ContractError::AdminProposalExpired {});
+        }
+
+        let mut ownership = load_ownership(deps.storage)?;
+        ownership.pending_admin = Some(new_admin.to_string());
+        ownership.pending_expiry = expiry;
+        OWNERSHIP.save(deps.storage, &ownership)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "propose_admin")
+            .add_attribute("pending_admin", new_admin)
+        )
+    }
+
+    pub fn accept_admin(deps: DepsMut, sender: Addr, env: Env) -> Result<Response, ContractError> {
+        let ownership = load_ownership(deps.storage)?;
+        match ownership.pending_admin {
+            None => return Err(ContractError::NoPendingAdmin {}),
+            Some(pending) if pending != sender.as_str() => return Err(ContractError::Unauthorized {}),
+            Some(_) => {}
+        }
+        if ownership.pending_expiry.map_or(false, |e| e.is_expired(&env.block)) {
+            return Err(ContractError::AdminProposalExpired {});
+        }
+
+        OWNERSHIP.save(deps.storage, &Ownership {
+            admin: Some(sender.to_string()),
+            pending_admin: None,
+            pending_expiry: None,
+        })?;
+
+        Ok(Response::new()
+            .add_attribute("action", "accept_admin")
+            .add_attribute("admin", sender)
+        )
+    }
+
+    pub fn renounce_admin(deps: DepsMut, sender: Addr) -> Result<Response, ContractError> {
+        assert_admin(deps.as_ref(), &sender)?;
+        OWNERSHIP.save(deps.storage, &Ownership {
+            admin: None,
+            pending_admin: None,
+            pending_expiry: None,
+        })?;
+
+        Ok(Response::new().add_attribute("action", "renounce_admin"))
+    }
+
+    pub fn set_metadata_editor(deps: DepsMut, editor: String, sender: Addr) -> Result<Response, ContractError> {
+        assert_admin(deps.as_ref(), &sender)?;
+        let editor = deps.api.addr_validate(&editor)?;
+        METADATA_EDITOR.save(deps.storage, &editor.to_string())?;
+
+        Ok(Response::new()
+            .add_attribute("action", "set_metadata_editor")
+            .add_attribute("editor", editor)
+        )
+    }
+
+    pub fn update_moderators(deps: DepsMut, add: Vec<String>, remove: Vec<String>, sender: Addr) -> Result<Response, ContractError> {
+        assert_admin(deps.as_ref(), &sender)?;
+
+        let mut moderators = MODERATORS.may_load(deps.storage)?.unwrap_or_default();
+        for moderator in add {
+            let moderator = deps.api.addr_validate(&moderator)?.to_string();
+            if !moderators.contains(&moderator) {
+                moderators.push(moderator);
+            }
+        }
+        moderators.retain(|m| !remove.contains(m));
+        MODERATORS.save(deps.storage, &moderators)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "update_moderators")
+            .add_attribute("moderators", moderators.join(","))
+        )
+    }
+
+    /// Listing and buying are paused while the collection is flagged
+    fn assert_not_flagged(s: &State) -> Result<(), ContractError> {
+        if s.flagged {
+            return Err(ContractError::Flagged {});
+        }
+        Ok(())
+    }
+
+    pub fn flag(enabled: bool, reason: Option<String>, deps: DepsMut, sender: Addr, env: Env) -> Result<Response, ContractError> {
+        let moderators = MODERATORS.may_load(deps.storage)?.unwrap_or_default();
+        if !moderators.iter().any(|m| m == sender.as_str()) {
+            assert_admin(deps.as_ref(), &sender)?;
+        }
+
         STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
             state.flagged = enabled;
+            state.flag_reason = reason.clone();
+            state.flag_time = Some(env.block.time.seconds());
             Ok(state)
-        }).unwrap();
+        })?;
 
-        Ok(Response::new())
+        Ok(Response::new()
+            .add_attribute("action", "flag")
+            .add_attribute("flagged", enabled.to_string())
+            .add_attribute("reason", reason.unwrap_or_default())
+        )
     }
 
     pub fn update_metadata(
@@ -159,15 +591,22 @@ pub mod execute {
     ) -> Result<Response, ContractError> {
         let s = STATE.load(deps.storage)?;
 
-        let creator = deps.querier.query_wasm_contract_info(s.contract).unwrap().creator;
-
-        if owner.as_str() != creator {
+        if owner.as_str() != load_metadata_editor(deps.storage)? {
             return Err(ContractError::Unauthorized {});
         }
+
+        let mut royalties = s.royalties;
+        if let Some(_creators) = creators {
+            royalties.creators = _creators;
+        }
+        if let Some(_basis_points) = basis_points {
+            royalties.seller_fee_basis_points = _basis_points as u32;
+        }
+        let royalties = validate_royalties(deps.as_ref(), royalties, &load_config(deps.storage)?)?;
+        let royalty_basis_points = royalties.seller_fee_basis_points;
+
         STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            if let Some(_creators) = creators {
-                state.royalties.creators = _creators;
-            }
+            state.royalties = royalties;
             if let Some(_description) = description {
                 state.description = _description;
             }
@@ -177,9 +616,6 @@ pub mod execute {
             if let Some(_banner_uri) = banner_uri {
                 state.banner_uri = _banner_uri;
             } 
-            if let Some(_basis_points) = basis_points {
-                state.royalties.seller_fee_basis_points = _basis_points as u32;
-            }
             if let Some(_collection) = collection {
                 state.collection = _collection;
             }
@@ -200,7 +636,10 @@ pub mod execute {
             }
             Ok(state)
         })?;
-        Ok(Response::new().add_attribute("action", "update_metadata"))
+        Ok(Response::new()
+            .add_attribute("action", "update_metadata")
+            .add_attribute("royalty_basis_points", royalty_basis_points.to_string())
+        )
     }
 
     #[derive(Debug, Serialize, Deserialize)]
@@ -208,128 +647,455 @@ pub mod execute {
         pub owner_of: OwnerOf
     }
 
-    pub fn list(deps: DepsMut, id: String, price: Uint128, expires: i128, owner: Addr) -> Result<Response, ContractError> {
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ApprovedForAllWrapper {
+        pub approved_for_all: ApprovedForAll
+    }
+
+    /// Everything needed to rebuild a listing from its event
+    pub fn listing_attributes(token: &Token) -> Vec<Attribute> {
+        let mut attrs = vec![
+            attr("token_id", &token.id),
+            attr("seller", &token.owner),
+            attr("price", token.price),
+            attr("denom", token.denom.to_string()),
+            attr("expires", token.expires.to_string()),
+            attr("escrowed", token.escrowed.to_string()),
+        ];
+        if let Some(buyer) = &token.reserved_for {
+            attrs.push(attr("reserved_for", buyer));
+        }
+        match &token.kind {
+            ListingKind::Fixed => attrs.push(attr("kind", "fixed")),
+            ListingKind::Dutch(dutch) => attrs.extend(vec![
+                attr("kind", "dutch"),
+                attr("end_price", dutch.end_price),
+                attr("start_time", dutch.start_time.to_string()),
+                attr("end_time", dutch.end_time.to_string()),
+                attr("decay", match dutch.decay {
+                    Decay::Linear => "linear",
+                    Decay::Exponential => "exponential",
+                }),
+            ]),
+        }
+        attrs
+    }
+
+    /// Dutch listings must fall from `price` to a lower end price over a non-empty window
+    fn validate_kind(price: Uint128, kind: &ListingKind) -> Result<(), ContractError> {
+        if let ListingKind::Dutch(dutch) = kind {
+            if dutch.end_price > price
+                || dutch.end_time <= dutch.start_time
+                || (dutch.decay == Decay::Exponential && dutch.end_price.is_zero())
+            {
+                return Err(ContractError::InvalidDutchAuction {});
+            }
+        }
+        Ok(())
+    }
+
+    /// Listings default to INJ. A native denom must be on the accepted list, a cw20 denom must be a valid contract address.
+    fn validate_denom(deps: Deps, denom: Option<Denom>) -> Result<Denom, ContractError> {
+        match denom.unwrap_or_default() {
+            Denom::Cw20(contract) => Ok(Denom::Cw20(deps.api.addr_validate(&contract)?.to_string())),
+            Denom::Native(denom) => {
+                let config = load_config(deps.storage)?;
+                if !config.accepted_denoms.contains(&denom) {
+                    return Err(ContractError::DenomNotAccepted { denom });
+                }
+                Ok(Denom::Native(denom))
+            }
+        }
+    }
+
+    pub fn list(deps: DepsMut, id: String, price: Uint128, expires: i128, kind: ListingKind, denom: Option<Denom>, reserved_for: Option<String>, owner: Addr, env: Env) -> Result<Response, ContractError> {
         let s = STATE.load(deps.storage)?;
+        assert_not_flagged(&s)?;
+        let token = add_approved_listing(deps, &s, ListItem { id, price, expires, denom, reserved_for }, kind, &owner, &env)?;
     
-        let resp: GetOwnerResponse = deps.querier.query_wasm_smart(
-            &s.contract, 
-            &QueryWrapper { owner_of: OwnerOf { token_id: id.clone() }}
-        ).unwrap();
+        Ok(Response::new()
+            .add_attribute("action", "list")
+            .add_attributes(listing_attributes(&token))
+        )
+    }
+
+    /// Lists a token the exchange is approved for, after checking `owner` holds it
+    fn add_approved_listing(deps: DepsMut, s: &State, item: ListItem, kind: ListingKind, owner: &Addr, env: &Env) -> Result<Token, ContractError> {
+        validate_kind(item.price, &kind)?;
+        let denom = validate_denom(deps.as_ref(), item.denom)?;
+        let reserved_for = item.reserved_for
+            .map(|buyer| deps.api.addr_validate(&buyer).map(|a| a.to_string()))
+            .transpose()?;
+    
+        let resp = query_owner(deps.as_ref(), &s.contract, &item.id)?;
     
         if owner.as_str() != resp.owner {
             return Err(ContractError::Unauthorized {});
         }
     
-        if resp.approvals.len() < 1 {
+        if !is_approved(deps.as_ref(), env, &s.contract, &resp)? {
+            return Err(ContractError::NotApproved {});
+        }
+
+        // an expired or stale listing can be replaced, a live one cannot
+        if let Some(existing) = listings().may_load(deps.storage, &item.id)? {
+            if !existing.is_expired(env.block.time) && is_live(deps.as_ref(), env, s, &existing) {
+                return Err(ContractError::Unauthorized {});
+            }
+            remove_listing(deps.storage, &item.id)?;
+        }
+
+        let token = Token {
+            id: item.id,
+            owner: owner.to_string(),
+            is_listed: true,
+            price: item.price,
+            expires: item.expires,
+            escrowed: false,
+            kind,
+            denom,
+            reserved_for,
+        };
+        if token.is_expired(env.block.time) {
+            return Err(ContractError::ListingExpired {});
+        }
+        add_listing(deps.storage, &token)?;
+        Ok(token)
+    }
+
+    pub fn batch_list(mut deps: DepsMut, items: Vec<ListItem>, owner: Addr, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        assert_not_flagged(&s)?;
+        if items.len() > MAX_BATCH_SIZE as usize {
+            return Err(ContractError::BatchTooLarge { max: MAX_BATCH_SIZE });
+        }
+
+        // one event, with the list attributes repeated for every token
+        let mut resp = Response::new()
+            .add_attribute("action", "batch_list")
+            .add_attribute("count", items.len().to_string());
+        for item in items {
+            let token = add_approved_listing(deps.branch(), &s, item, ListingKind::Fixed, &owner, &env)?;
+            resp = resp.add_attributes(listing_attributes(&token));
+        }
+        Ok(resp)
+    }
+
+    pub fn update_price(deps: DepsMut, id: String, price: Uint128, expires: i128, owner: Addr, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        assert_not_flagged(&s)?;
+        let mut token = listings().may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
+        if owner != token.owner {
             return Err(ContractError::Unauthorized {});
         }
+        // an expired or stale listing has to be listed again, with the usual checks
+        if token.is_expired(env.block.time) {
+            return Err(ContractError::ListingExpired {});
+        }
+        if !is_live(deps.as_ref(), &env, &s, &token) {
+            return Err(ContractError::StaleListing {});
+        }
+
+        validate_kind(price, &token.kind)?;
+        token.price = price;
+        token.expires = expires;
+        if token.is_expired(env.block.time) {
+            return Err(ContractError::ListingExpired {});
+        }
+        // same id, so the listing count doesn't change
+        listings().save(deps.storage, &id, &token)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "update_price")
+            .add_attributes(listing_attributes(&token))
+        )
+    }
 
-        let dup = check_duplicate(&s.listed, &id);
-        if dup.is_some() {
+    pub fn receive_nft(deps: DepsMut, msg: Cw721ReceiveMsg, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+
+        // only tokens from this exchange's collection can be escrowed
+        if info.sender != s.contract {
             return Err(ContractError::Unauthorized {});
         }
-    
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            if let Some(token) = state.listed.iter_mut().find(|token| token.id == id) {
-                // If the token with the given id already exists, update the price and expiration
-                token.price = price;
-                token.expires = expires;
-            } else {
-                // If the token with the given id doesn't exist, add a new token to the `listed` vector
-                state.listed.push(Token {
-                    id: id.to_string(),
-                    owner: owner.to_string(),
-                    is_listed: true,
-                    price: price,
-                    expires: expires,
-                });
+        assert_not_flagged(&s)?;
+
+        match from_binary(&msg.msg)? {
+            ReceiveNftMsg::List { price, expires, denom } => list_escrow(deps, msg, price, expires, ListingKind::Fixed, denom, env),
+            ReceiveNftMsg::ListDutch { price, dutch, expires, denom } => list_escrow(deps, msg, price, expires, ListingKind::Dutch(dutch), denom, env),
+            ReceiveNftMsg::Auction { reserve_price, min_increment, end_time } => {
+                let seller = deps.api.addr_validate(&msg.sender)?;
+
+                if end_time <= env.block.time.seconds() {
+                    return Err(ContractError::AuctionEnded {});
+                }
+                // otherwise a bid matching the best one would take the lead
+                if min_increment.is_zero() {
+                    return Err(ContractError::InvalidIncrement {});
+                }
+
+                // the token can only be sold one way at a time
+                if listings().has(deps.storage, &msg.token_id) {
+                    remove_listing(deps.storage, &msg.token_id)?;
+                }
+                AUCTIONS.save(deps.storage, &msg.token_id, &Auction {
+                    token_id: msg.token_id.clone(),
+                    seller: seller.to_string(),
+                    reserve_price,
+                    min_increment,
+                    end_time,
+                    highest_bid: None,
+                })?;
+
+                Ok(Response::new()
+                    .add_attribute("action", "start_auction")
+                    .add_attribute("token_id", msg.token_id)
+                    .add_attribute("seller", seller)
+                    .add_attribute("reserve_price", reserve_price)
+                    .add_attribute("min_increment", min_increment)
+                    .add_attribute("denom", Denom::default().to_string())
+                    .add_attribute("end_time", end_time.to_string())
+                )
             }
-            state.listed.sort_by_key(|i| i.price.u128());
-            Ok(state)
-        })?;
-    
-        Ok(Response::new().add_attribute("action", "increment"))
+        }
+    }
+
+    fn list_escrow(deps: DepsMut, msg: Cw721ReceiveMsg, price: Uint128, expires: i128, kind: ListingKind, denom: Option<Denom>, env: Env) -> Result<Response, ContractError> {
+        let owner = deps.api.addr_validate(&msg.sender)?;
+        validate_kind(price, &kind)?;
+        let denom = validate_denom(deps.as_ref(), denom)?;
+
+        let token = Token {
+            id: msg.token_id.clone(),
+            owner: owner.to_string(),
+            is_listed: true,
+            price,
+            expires,
+            escrowed: true,
+            kind,
+            denom,
+            reserved_for: None,
+        };
+        if token.is_expired(env.block.time) {
+            return Err(ContractError::ListingExpired {});
+        }
+
+        // the exchange now holds the token, so any approval-based listing is stale
+        if listings().has(deps.storage, &msg.token_id) {
+            remove_listing(deps.storage, &msg.token_id)?;
+        }
+        add_listing(deps.storage, &token)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "list")
+            .add_attributes(listing_attributes(&token))
+        )
     }
 
-    pub fn buy(deps: DepsMut, id: String, info: &MessageInfo, _env: Env) -> Result<Response, ContractError> {
-        let mut s = STATE.load(deps.storage)?;
+    pub fn buy(deps: DepsMut, id: String, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let token = listings().may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
+
+        let payment: Uint128 = match &token.denom {
+            Denom::Native(denom) => cw_utils::must_pay(info, denom).map_err(|_| ContractError::InsufficientFunds {})?,
+            // cw20 listings are bought by sending the tokens, see `receive`
+            Denom::Cw20(_) => return Err(ContractError::InvalidDenom {}),
+        };
+
+        settle_buy(deps, token, info.sender.to_string(), payment, env)
+    }
+
+    /// cw20 `send` hook, used to buy listings priced in that cw20
+    pub fn receive(deps: DepsMut, msg: Cw20ReceiveMsg, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        match from_binary(&msg.msg)? {
+            Cw20HookMsg::Buy { id } => {
+                let token = listings().may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
+                if token.denom != Denom::Cw20(info.sender.to_string()) {
+                    return Err(ContractError::InvalidDenom {});
+                }
+
+                let buyer = deps.api.addr_validate(&msg.sender)?;
+                settle_buy(deps, token, buyer.to_string(), msg.amount, env)
+            }
+        }
+    }
+
+    /// Pays out a sale of a listed token once `payment` in the listing's denom has been received
+    fn settle_buy(deps: DepsMut, token: Token, buyer: String, payment: Uint128, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        assert_not_flagged(&s)?;
         let address = &s.contract;
 
-        let mut resp = Response::new();
+        if token.reserved_for_other(&buyer) {
+            return Err(ContractError::Reserved {});
+        }
+        if token.is_expired(env.block.time) {
+            return Err(ContractError::ListingExpired {});
+        }
+        // the token may have moved or lost its approval since it was listed
+        if !is_live(deps.as_ref(), &env, &s, &token) {
+            return Err(ContractError::StaleListing {});
+        }
+
+        // dutch listings are priced at the time of purchase
+        let price = token.current_price(env.block.time);
+        if payment < price {
+            return Err(ContractError::InsufficientFunds {});
+        }
+
+        // payments to creators, fee wallet and owner, and send_token to buyer
+        let breakdown = payout::split(price, token.denom.clone(), &token.owner, &s.royalties, &load_config(deps.storage)?)?;
+        record_sale(deps.storage, token.id.clone(), token.owner.clone(), buyer.clone(), price, token.denom.clone(), env.block.time.seconds())?;
+        let mut resp = Response::new()
+            .add_messages(payout::messages(&breakdown)?)
+            .add_message(send_token(address, buyer.clone(), token.id.to_string())?)
+            .add_attribute("action", "buy")
+            .add_attribute("token_id", &token.id)
+            .add_attribute("buyer", &buyer)
+            .add_attributes(payout::attributes(&breakdown));
+        if payment > price {
+            resp = resp.add_message(payout::payment_msg(&token.denom, buyer, payment - price)?);
+        }
+
+        remove_listing(deps.storage, &token.id)?;
+
+        Ok(resp)
+    }
+
+    /// Buys the cheapest live listings in the attached denom that fit the count and budget,
+    /// paying each recipient once for all of them
+    pub fn sweep(deps: DepsMut, max_count: u32, max_total_price: Uint128, ids: Option<Vec<String>>, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        assert_not_flagged(&s)?;
+        if max_count == 0 {
+            return Err(ContractError::InvalidQuantity {});
+        }
+        if max_count > MAX_BATCH_SIZE || ids.as_ref().map_or(false, |ids| ids.len() > MAX_BATCH_SIZE as usize) {
+            return Err(ContractError::BatchTooLarge { max: MAX_BATCH_SIZE });
+        }
+        let payment = cw_utils::one_coin(info).map_err(|_| ContractError::InsufficientFunds {})?;
+        let denom = Denom::Native(payment.denom);
+        let budget = max_total_price.min(payment.amount);
+        let buyer = info.sender.to_string();
 
-        for (i, token) in s.listed.iter_mut().enumerate() {
-            // This gets a bit messy, but block.time.seconds is a u64. Timestamps are huge numbers, so we need to convert to u128
-            // if token.expires as u128 <= env.block.time.seconds() as u128 {
-            //     STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            //         state.listed.remove(i-1);
-            //         Ok(state)
-            //     })?;
-            // }
-            if token.id == id {
-                let payment: Uint128 = cw_utils::must_pay(info, "inj").unwrap();
-                let royalty_rate: Decimal = Decimal::from_ratio(s.royalties.seller_fee_basis_points as u128, 10_000 as u128);
-                let royalty_amount = token.price * royalty_rate;
-                let mut payout = token.price - royalty_amount;
-                payout = Uint128::from(payout.u128() - (payout * Decimal::percent(3)).u128()); // 3% platform fee
-                if (token.price + royalty_amount) > payment { // need to rework this to include platform fee and royalties
-                    return Err(ContractError::InsufficientFunds {});
+        // a listing can be bought if it's live, in this denom, not the buyer's own and not
+        // reserved for someone else
+        let buyable = |token: &Token| {
+            token.denom == denom
+                && token.owner != buyer
+                && !token.reserved_for_other(&buyer)
+                && !token.is_expired(env.block.time)
+                && is_live(deps.as_ref(), &env, &s, token)
+        };
+        let mut candidates: Vec<(Uint128, Token)> = vec![];
+        match ids {
+            Some(mut ids) => {
+                ids.sort();
+                ids.dedup();
+                for id in ids {
+                    if let Some(token) = listings().may_load(deps.storage, &id)? {
+                        if buyable(&token) {
+                            keep_cheapest(&mut candidates, token, max_count, env.block.time);
+                        }
+                    }
                 }
-                else {
-                    // Execute send_token(info.sender, token.id) from the contract
-
-                    // create vec of messages; bankMsgSend to creators, bankMsgSend to fee wallet, bankMsgSend to owner, and send_token to buyer
-                    resp = resp.add_messages(s.royalties.creators.iter().map(|creator| {
-                        let creator_addr = Addr::unchecked(&creator.address);
-                        return BankMsg::Send {    
-                            to_address: creator_addr.into(), 
-                            amount: coins((royalty_amount * Decimal::percent(creator.share as u64)).u128(), "inj"),
-                        };
-                    }).rev())
-                    .add_message(BankMsg::Send {  
-                        to_address: "inj1f4psdn7c7ap3aruu5zpex5p9a05k8qd077736v".into(),
-                        amount: coins((payment * Decimal::percent(3)).u128(), "inj"),
-                    })
-                    .add_message(BankMsg::Send { to_address: String::from(&token.owner), amount: coins(payout.u128(), "inj") })
-                    .add_messages(vec![MsgExecuteContract {
-                       contract_addr: address.into(),
-                       msg: to_binary(&Tmessage { transfer_nft: SendTokenMsg { recipient: info.sender.to_string(), token_id: token.id.to_string() } }).unwrap(),
-                       funds: vec![],
-                    }]);
-
-                    STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-                        state.listed.remove(i);
-                        state.listed.sort_by_key(|i| i.price.u128());
-                        Ok(state)
-                    })?;
-                    break;
+            }
+            None => {
+                // same walk as the floor query: once the lowest price a listing can reach is over
+                // budget, or no better than the dearest of a full set, nothing further can be bought
+                for item in listings().idx.floor.sub_prefix(denom.to_string()).range(deps.storage, None, None, Order::Ascending) {
+                    let (_, token) = item?;
+                    if token.lowest_price() > budget {
+                        break;
+                    }
+                    if candidates.len() == max_count as usize
+                        && candidates.last().map_or(false, |(price, _)| token.lowest_price() >= *price)
+                    {
+                        break;
+                    }
+                    if buyable(&token) {
+                        keep_cheapest(&mut candidates, token, max_count, env.block.time);
+                    }
                 }
             }
         }
-        Ok(resp)
+
+        // cheapest first, so the first one over budget ends the sweep
+        let config = load_config(deps.storage)?;
+        let mut spent = Uint128::zero();
+        let mut breakdowns = vec![];
+        let mut transfers = vec![];
+        let mut sale_attributes = vec![];
+        for (price, token) in candidates {
+            if spent + price > budget {
+                break;
+            }
+            spent += price;
+            let breakdown = payout::split(price, denom.clone(), &token.owner, &s.royalties, &config)?;
+            record_sale(deps.storage, token.id.clone(), token.owner.clone(), buyer.clone(), price, denom.clone(), env.block.time.seconds())?;
+            remove_listing(deps.storage, &token.id)?;
+            transfers.push(send_token(&s.contract, buyer.clone(), token.id.clone())?);
+            sale_attributes.push(attr("token_id", &token.id));
+            sale_attributes.extend(payout::attributes(&breakdown));
+            breakdowns.push(breakdown);
+        }
+        if breakdowns.is_empty() {
+            return Err(ContractError::NothingToSweep {});
+        }
+
+        // one payment per recipient across every sale, with the buyer's change folded in
+        let mut payments = payout::totals(&breakdowns);
+        let refund = payment.amount - spent;
+        if !refund.is_zero() {
+            match payments.iter_mut().find(|p| p.address == buyer) {
+                Some(p) => p.amount += refund,
+                None => payments.push(Payment { address: buyer.clone(), amount: refund }),
+            }
+        }
+        let payments = payments
+            .into_iter()
+            .map(|p| payout::payment_msg(&denom, p.address, p.amount))
+            .collect::<StdResult<Vec<_>>>()?;
+
+        Ok(Response::new()
+            .add_messages(transfers)
+            .add_messages(payments)
+            .add_attribute("action", "sweep")
+            .add_attribute("buyer", &buyer)
+            .add_attribute("denom", denom.to_string())
+            .add_attribute("count", breakdowns.len().to_string())
+            .add_attribute("total", spent)
+            .add_attribute("refund", refund)
+            .add_attributes(sale_attributes)
+        )
+    }
+
+    /// Adds `token` to `candidates` at its current price, keeping only the `max_count` cheapest
+    fn keep_cheapest(candidates: &mut Vec<(Uint128, Token)>, token: Token, max_count: u32, now: Timestamp) {
+        candidates.push((token.current_price(now), token));
+        candidates.sort_by(|a, b| a.0.cmp(&b.0).then_with(|| a.1.id.cmp(&b.1.id)));
+        candidates.truncate(max_count as usize);
     }
 
     pub fn delist(deps: DepsMut, id: String, info: &MessageInfo, _env: Env) -> Result<Response, ContractError> {
         let s = STATE.load(deps.storage)?;
+        let token = listings().may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
 
-        let index = check_duplicate(&s.listed, &id);
-
-        if info.sender != s.listed[index.expect("index is none")].owner {
+        if info.sender != token.owner {
             return Err(ContractError::Unauthorized {});
         }
 
-        if index.is_none() {
-            return Err(ContractError::NotFound {});
-        }
+        remove_listing(deps.storage, &id)?;
 
-        STATE.update(deps.storage, |mut state| -> Result<_, ContractError> {
-            state.listed.remove(index.expect("index is none"));
-            state.listed.sort_by_key(|i| i.price.u128());
-            Ok(state)
-        })?;
+        let mut resp = Response::new()
+            .add_attribute("action", "delist")
+            .add_attribute("token_id", &token.id)
+            .add_attribute("seller", &token.owner);
+        if token.escrowed {
+            resp = resp.add_message(send_token(&s.contract, token.owner, token.id)?);
+        }
 
         return Ok(
-            Response::new()
-            .add_attribute("action", "delist")
+            resp
             // .add_message(
             //     MsgExecuteContract { 
             //         contract_addr: s.contract, 
@@ -346,23 +1112,581 @@ pub mod execute {
             // )
         );
     }
+
+    pub fn batch_delist(deps: DepsMut, ids: Vec<String>, owner: Addr) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        if ids.len() > MAX_BATCH_SIZE as usize {
+            return Err(ContractError::BatchTooLarge { max: MAX_BATCH_SIZE });
+        }
+
+        let mut resp = Response::new()
+            .add_attribute("action", "batch_delist")
+            .add_attribute("seller", owner.to_string())
+            .add_attribute("count", ids.len().to_string())
+            .add_attribute("token_ids", ids.join(","));
+        for id in ids {
+            let token = listings().may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
+            if owner != token.owner {
+                return Err(ContractError::Unauthorized {});
+            }
+            remove_listing(deps.storage, &id)?;
+            if token.escrowed {
+                resp = resp.add_message(send_token(&s.contract, token.owner, token.id)?);
+            }
+        }
+        Ok(resp)
+    }
+
+    pub fn invalidate_listing(deps: DepsMut, id: String, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        let token = listings().may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
+        if is_live(deps.as_ref(), &env, &s, &token) {
+            return Err(ContractError::ListingNotStale {});
+        }
+
+        remove_listing(deps.storage, &id)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "invalidate_listing")
+            .add_attribute("token_id", id)
+            .add_attribute("seller", token.owner)
+        )
+    }
+
+    pub fn purge_expired(deps: DepsMut, limit: Option<u32>, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let now = env.block.time.seconds() as i128;
+
+        // 0 means "never expires", so start the walk at 1
+        let expired = listings()
+            .idx
+            .expires
+            .range(
+                deps.storage,
+                Some(Bound::inclusive((1i128, String::new()))),
+                Some(Bound::exclusive((now + 1, String::new()))),
+                Order::Ascending,
+            )
+            .take(limit)
+            .map(|item| item.map(|(_, token)| token))
+            .collect::<StdResult<Vec<Token>>>()?;
+
+        let mut resp = Response::new();
+        for token in &expired {
+            remove_listing(deps.storage, &token.id)?;
+            // escrowed tokens go back to their owner
+            if token.escrowed {
+                resp = resp.add_message(send_token(&s.contract, token.owner.clone(), token.id.clone())?);
+            }
+        }
+
+        Ok(resp
+            .add_attribute("action", "purge_expired")
+            .add_attribute("purged", expired.len().to_string())
+            .add_attribute("token_ids", expired.iter().map(|t| t.id.as_str()).collect::<Vec<&str>>().join(","))
+        )
+    }
+
+    pub fn migrate_listings(deps: DepsMut, limit: Option<u32>) -> Result<Response, ContractError> {
+        let limit = limit.unwrap_or(MAX_BATCH_SIZE).min(MAX_BATCH_SIZE) as usize;
+        let (migrated, remaining) = migrate_legacy_listings(deps.storage, limit)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "migrate_listings")
+            .add_attributes(migrated_listing_attributes(&migrated, remaining))
+        )
+    }
+
+    pub fn make_offer(deps: DepsMut, id: String, expires: i128, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let price: Uint128 = cw_utils::must_pay(info, "inj").map_err(|_| ContractError::InsufficientFunds {})?;
+
+        let offer = Offer {
+            token_id: id.clone(),
+            bidder: info.sender.to_string(),
+            price,
+            expires,
+        };
+        if offer.is_expired(env.block.time) {
+            return Err(ContractError::OfferExpired {});
+        }
+
+        // a new offer replaces the bidder's previous one on this token
+        let mut resp = Response::new();
+        if let Some(previous) = offers().may_load(deps.storage, (id.as_str(), info.sender.as_str()))? {
+            resp = resp.add_message(BankMsg::Send {
+                to_address: previous.bidder,
+                amount: coins(previous.price.u128(), "inj"),
+            });
+        }
+        offers().save(deps.storage, (id.as_str(), info.sender.as_str()), &offer)?;
+
+        Ok(resp
+            .add_attribute("action", "make_offer")
+            .add_attribute("token_id", id)
+            .add_attribute("bidder", info.sender.to_string())
+            .add_attribute("price", price)
+        )
+    }
+
+    pub fn cancel_offer(deps: DepsMut, id: String, info: &MessageInfo) -> Result<Response, ContractError> {
+        let offer = offers()
+            .may_load(deps.storage, (id.as_str(), info.sender.as_str()))?
+            .ok_or(ContractError::NotFound {})?;
+        offers().remove(deps.storage, (id.as_str(), info.sender.as_str()))?;
+
+        Ok(Response::new()
+            .add_message(BankMsg::Send {
+                to_address: offer.bidder,
+                amount: coins(offer.price.u128(), "inj"),
+            })
+            .add_attribute("action", "cancel_offer")
+            .add_attribute("token_id", id)
+            .add_attribute("bidder", info.sender.to_string())
+        )
+    }
+
+    /// Checks that `sender` may sell token `id` and clears any listing on it.
+    /// The seller is the cw721 owner, or the lister if the exchange holds the token in escrow.
+    fn take_for_sale(deps: DepsMut, s: &State, id: &str, sender: &Addr, env: &Env) -> Result<String, ContractError> {
+        assert_not_flagged(s)?;
+        let owner = query_owner(deps.as_ref(), &s.contract, id)?.owner;
+        let listing = listings().may_load(deps.storage, id)?;
+        let seller = match &listing {
+            Some(token) if token.escrowed && owner == env.contract.address.as_str() => token.owner.clone(),
+            _ => owner,
+        };
+        if sender != &seller {
+            return Err(ContractError::Unauthorized {});
+        }
+
+        if listing.is_some() {
+            remove_listing(deps.storage, id)?;
+        }
+        Ok(seller)
+    }
+
+    pub fn accept_offer(mut deps: DepsMut, id: String, bidder: String, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+
+        let offer = offers()
+            .may_load(deps.storage, (id.as_str(), bidder.as_str()))?
+            .ok_or(ContractError::NotFound {})?;
+        if offer.is_expired(env.block.time) {
+            return Err(ContractError::OfferExpired {});
+        }
+
+        let seller = take_for_sale(deps.branch(), &s, &id, &info.sender, &env)?;
+        offers().remove(deps.storage, (id.as_str(), bidder.as_str()))?;
+
+        let breakdown = payout::split(offer.price, Denom::default(), &seller, &s.royalties, &load_config(deps.storage)?)?;
+        record_sale(deps.storage, id.clone(), seller, offer.bidder.clone(), offer.price, Denom::default(), env.block.time.seconds())?;
+        Ok(Response::new()
+            .add_messages(payout::messages(&breakdown)?)
+            .add_message(send_token(&s.contract, offer.bidder.clone(), id.clone())?)
+            .add_attribute("action", "accept_offer")
+            .add_attribute("token_id", id)
+            .add_attribute("buyer", offer.bidder)
+            .add_attributes(payout::attributes(&breakdown))
+        )
+    }
+
+    pub fn make_collection_offer(deps: DepsMut, price: Uint128, quantity: u32, expires: i128, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        let payment: Uint128 = cw_utils::must_pay(info, "inj").map_err(|_| ContractError::InsufficientFunds {})?;
+
+        if quantity == 0 {
+            return Err(ContractError::InvalidQuantity {});
+        }
+        // nothing would be escrowed, and accepting would hand a token over for free
+        if price.is_zero() {
+            return Err(ContractError::InsufficientFunds {});
+        }
+        let total = price.checked_mul(Uint128::from(quantity)).map_err(StdError::from)?;
+        if payment < total {
+            return Err(ContractError::InsufficientFunds {});
+        }
+
+        let id = COLLECTION_OFFER_SEQ.may_load(deps.storage)?.unwrap_or_default() + 1;
+        COLLECTION_OFFER_SEQ.save(deps.storage, &id)?;
+
+        let offer = CollectionOffer {
+            id,
+            collection: s.contract,
+            bidder: info.sender.to_string(),
+            price,
+            quantity,
+            expires,
+        };
+        if offer.is_expired(env.block.time) {
+            return Err(ContractError::OfferExpired {});
+        }
+        collection_offers().save(deps.storage, id, &offer)?;
+
+        let mut resp = Response::new();
+        if payment > total {
+            resp = resp.add_message(BankMsg::Send {
+                to_address: info.sender.to_string(),
+                amount: coins((payment - total).u128(), "inj"),
+            });
+        }
+
+        Ok(resp
+            .add_attribute("action", "make_collection_offer")
+            .add_attribute("offer_id", id.to_string())
+            .add_attribute("bidder", info.sender.to_string())
+            .add_attribute("price", price)
+            .add_attribute("quantity", quantity.to_string())
+        )
+    }
+
+    pub fn cancel_collection_offer(deps: DepsMut, offer_id: u64, info: &MessageInfo) -> Result<Response, ContractError> {
+        let offer = collection_offers()
+            .may_load(deps.storage, offer_id)?
+            .ok_or(ContractError::NotFound {})?;
+        if info.sender != offer.bidder {
+            return Err(ContractError::Unauthorized {});
+        }
+        collection_offers().remove(deps.storage, offer_id)?;
+
+        // only the unfilled part is still escrowed
+        let refund = offer.price * Uint128::from(offer.quantity);
+
+        Ok(Response::new()
+            .add_message(BankMsg::Send {
+                to_address: offer.bidder,
+                amount: coins(refund.u128(), "inj"),
+            })
+            .add_attribute("action", "cancel_collection_offer")
+            .add_attribute("offer_id", offer_id.to_string())
+        )
+    }
+
+    pub fn accept_collection_offer(mut deps: DepsMut, offer_id: u64, token_id: String, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+
+        let mut offer = collection_offers()
+            .may_load(deps.storage, offer_id)?
+            .ok_or(ContractError::NotFound {})?;
+        if offer.is_expired(env.block.time) {
+            return Err(ContractError::OfferExpired {});
+        }
+
+        let seller = take_for_sale(deps.branch(), &s, &token_id, &info.sender, &env)?;
+
+        offer.quantity -= 1;
+        if offer.quantity == 0 {
+            collection_offers().remove(deps.storage, offer_id)?;
+        } else {
+            collection_offers().save(deps.storage, offer_id, &offer)?;
+        }
+
+        let breakdown = payout::split(offer.price, Denom::default(), &seller, &s.royalties, &load_config(deps.storage)?)?;
+        record_sale(deps.storage, token_id.clone(), seller, offer.bidder.clone(), offer.price, Denom::default(), env.block.time.seconds())?;
+        Ok(Response::new()
+            .add_messages(payout::messages(&breakdown)?)
+            .add_message(send_token(&s.contract, offer.bidder.clone(), token_id.clone())?)
+            .add_attribute("action", "accept_collection_offer")
+            .add_attribute("offer_id", offer_id.to_string())
+            .add_attribute("token_id", token_id)
+            .add_attribute("buyer", offer.bidder)
+            .add_attributes(payout::attributes(&breakdown))
+        )
+    }
+
+    pub fn place_bid(deps: DepsMut, id: String, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        // settling and cancelling stay open while flagged, so funds and tokens can be recovered
+        assert_not_flagged(&STATE.load(deps.storage)?)?;
+        let mut auction = AUCTIONS.may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
+        let now = env.block.time.seconds();
+        if now >= auction.end_time {
+            return Err(ContractError::AuctionEnded {});
+        }
+
+        let amount: Uint128 = cw_utils::must_pay(info, "inj").map_err(|_| ContractError::InsufficientFunds {})?;
+        let min = match &auction.highest_bid {
+            Some(bid) => bid.amount + auction.min_increment,
+            None => auction.reserve_price,
+        };
+        if amount < min {
+            return Err(ContractError::BidTooLow { min });
+        }
+
+        let mut resp = Response::new();
+        if let Some(outbid) = auction.highest_bid.take() {
+            resp = resp.add_message(BankMsg::Send {
+                to_address: outbid.bidder,
+                amount: coins(outbid.amount.u128(), "inj"),
+            });
+        }
+        auction.highest_bid = Some(Bid { bidder: info.sender.to_string(), amount });
+
+        // anti-sniping: a late bid gives everyone else time to respond
+        if auction.end_time - now < AUCTION_EXTENSION_SECONDS {
+            auction.end_time = now + AUCTION_EXTENSION_SECONDS;
+        }
+        AUCTIONS.save(deps.storage, &id, &auction)?;
+
+        Ok(resp
+            .add_attribute("action", "place_bid")
+            .add_attribute("token_id", id)
+            .add_attribute("bidder", info.sender.to_string())
+            .add_attribute("amount", amount)
+            .add_attribute("end_time", auction.end_time.to_string())
+        )
+    }
+
+    pub fn settle_auction(deps: DepsMut, id: String, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        let auction = AUCTIONS.may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
+        if env.block.time.seconds() < auction.end_time {
+            return Err(ContractError::AuctionNotEnded {});
+        }
+        AUCTIONS.remove(deps.storage, &id);
+
+        let resp = Response::new()
+            .add_attribute("action", "settle_auction")
+            .add_attribute("token_id", &id);
+        Ok(match auction.highest_bid {
+            Some(bid) => {
+                let breakdown = payout::split(bid.amount, Denom::default(), &auction.seller, &s.royalties, &load_config(deps.storage)?)?;
+                record_sale(deps.storage, id.clone(), auction.seller, bid.bidder.clone(), bid.amount, Denom::default(), env.block.time.seconds())?;
+                resp
+                    .add_messages(payout::messages(&breakdown)?)
+                    .add_message(send_token(&s.contract, bid.bidder.clone(), id)?)
+                    .add_attribute("buyer", bid.bidder)
+                    .add_attributes(payout::attributes(&breakdown))
+            }
+            // no bids, the token goes back to the seller
+            None => resp
+                .add_message(send_token(&s.contract, auction.seller.clone(), id)?)
+                .add_attribute("seller", auction.seller),
+        })
+    }
+
+    pub fn cancel_auction(deps: DepsMut, id: String, info: &MessageInfo) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        let auction = AUCTIONS.may_load(deps.storage, &id)?.ok_or(ContractError::NotFound {})?;
+        if info.sender != auction.seller {
+            return Err(ContractError::Unauthorized {});
+        }
+        if auction.highest_bid.is_some() {
+            return Err(ContractError::AuctionHasBids {});
+        }
+        AUCTIONS.remove(deps.storage, &id);
+
+        Ok(Response::new()
+            .add_message(send_token(&s.contract, auction.seller, id.clone())?)
+            .add_attribute("action", "cancel_auction")
+            .add_attribute("token_id", id)
+        )
+    }
+
+    /// Checks `holder` can hand over `item`: it's either escrowed in their listing here, or
+    /// theirs with the exchange approved to transfer it
+    fn check_holding(deps: Deps, env: &Env, s: &State, item: &SwapItem, holder: &str) -> Result<(), ContractError> {
+        let resp = query_owner(deps, &item.contract, &item.token_id)?;
+        if item.contract == s.contract && resp.owner == env.contract.address.as_str() {
+            return match listings().may_load(deps.storage, &item.token_id)? {
+                Some(token) if token.escrowed && token.owner == holder => Ok(()),
+                _ => Err(ContractError::Unauthorized {}),
+            };
+        }
+        if resp.owner != holder {
+            return Err(ContractError::Unauthorized {});
+        }
+        if !is_approved(deps, env, &item.contract, &resp)? {
+            return Err(ContractError::NotApproved {});
+        }
+        Ok(())
+    }
+
+    pub fn propose_swap(deps: DepsMut, offered: Vec<SwapItem>, requested: Vec<String>, expires: i128, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        assert_not_flagged(&s)?;
+        if offered.len() > MAX_BATCH_SIZE as usize || requested.len() > MAX_BATCH_SIZE as usize {
+            return Err(ContractError::BatchTooLarge { max: MAX_BATCH_SIZE });
+        }
+
+        // the cash leg is optional, in any accepted native denom
+        let (price, denom) = if info.funds.is_empty() {
+            (Uint128::zero(), Denom::default())
+        } else {
+            let payment = cw_utils::one_coin(info).map_err(|_| ContractError::InsufficientFunds {})?;
+            (payment.amount, validate_denom(deps.as_ref(), Some(Denom::Native(payment.denom)))?)
+        };
+
+        let offered = offered
+            .into_iter()
+            .map(|item| Ok(SwapItem { contract: deps.api.addr_validate(&item.contract)?.to_string(), token_id: item.token_id }))
+            .collect::<StdResult<Vec<SwapItem>>>()?;
+        let distinct = {
+            let mut seen = std::collections::HashSet::new();
+            offered
+                .iter()
+                .map(|item| (item.contract.as_str(), item.token_id.as_str()))
+                .chain(requested.iter().map(|id| (s.contract.as_str(), id.as_str())))
+                .all(|key| seen.insert(key))
+        };
+        if !distinct || requested.is_empty() || (offered.is_empty() && price.is_zero()) {
+            return Err(ContractError::InvalidSwap {});
+        }
+
+        for item in &offered {
+            check_holding(deps.as_ref(), &env, &s, item, info.sender.as_str())?;
+        }
+        // requested tokens must exist, whoever ends up holding them
+        for id in &requested {
+            query_owner(deps.as_ref(), &s.contract, id)?;
+        }
+
+        let id = SWAP_SEQ.may_load(deps.storage)?.unwrap_or_default() + 1;
+        SWAP_SEQ.save(deps.storage, &id)?;
+        let swap = Swap {
+            id,
+            proposer: info.sender.to_string(),
+            offered,
+            requested,
+            price,
+            denom,
+            expires,
+        };
+        if swap.is_expired(env.block.time) {
+            return Err(ContractError::OfferExpired {});
+        }
+        swaps().save(deps.storage, id, &swap)?;
+
+        Ok(Response::new()
+            .add_attribute("action", "propose_swap")
+            .add_attribute("swap_id", id.to_string())
+            .add_attribute("proposer", swap.proposer)
+            .add_attribute("offered", swap.offered.iter().map(|i| format!("{}:{}", i.contract, i.token_id)).collect::<Vec<String>>().join(","))
+            .add_attribute("requested", swap.requested.join(","))
+            .add_attribute("price", swap.price)
+            .add_attribute("denom", swap.denom.to_string())
+            .add_attribute("expires", swap.expires.to_string())
+        )
+    }
+
+    pub fn accept_swap(deps: DepsMut, id: u64, info: &MessageInfo, env: Env) -> Result<Response, ContractError> {
+        let s = STATE.load(deps.storage)?;
+        assert_not_flagged(&s)?;
+        let swap = swaps().may_load(deps.storage, id)?.ok_or(ContractError::NotFound {})?;
+        if swap.is_expired(env.block.time) {
+            return Err(ContractError::OfferExpired {});
+        }
+
+        let acceptor = info.sender.to_string();
+        let requested: Vec<SwapItem> = swap.requested
+            .iter()
+            .map(|token_id| SwapItem { contract: s.contract.clone(), token_id: token_id.clone() })
+            .collect();
+        for item in &swap.offered {
+            check_holding(deps.as_ref(), &env, &s, item, &swap.proposer).map_err(|_| ContractError::StaleSwap {})?;
+        }
+        for item in &requested {
+            check_holding(deps.as_ref(), &env, &s, item, &acceptor)?;
+        }
+        swaps().remove(deps.storage, id)?;
+
+        // every token of this collection that changes hands leaves the order book
+        let mut resp = Response::new();
+        for (items, recipient) in [(&swap.offered, &acceptor), (&requested, &swap.proposer)] {
+            for item in items {
+                if item.contract == s.contract {
+                    remove_listing(deps.storage, &item.token_id)?;
+                }
+                resp = resp.add_message(send_token(&item.contract, recipient.clone(), item.token_id.clone())?);
+            }
+        }
+        resp = resp
+            .add_attribute("action", "accept_swap")
+            .add_attribute("swap_id", id.to_string())
+            .add_attribute("proposer", &swap.proposer)
+            .add_attribute("acceptor", &acceptor)
+            .add_attribute("offered", swap.offered.iter().map(|i| format!("{}:{}", i.contract, i.token_id)).collect::<Vec<String>>().join(","))
+            .add_attribute("requested", swap.requested.join(","));
+
+        // the cash leg buys this collection's tokens, so it pays royalties and the fee like a sale
+        if !swap.price.is_zero() {
+            let breakdown = payout::split(swap.price, swap.denom, &acceptor, &s.royalties, &load_config(deps.storage)?)?;
+            resp = resp
+                .add_messages(payout::messages(&breakdown)?)
+                .add_attributes(payout::attributes(&breakdown));
+        }
+        Ok(resp)
+    }
+
+    pub fn cancel_swap(deps: DepsMut, id: u64, info: &MessageInfo) -> Result<Response, ContractError> {
+        let swap = swaps().may_load(deps.storage, id)?.ok_or(ContractError::NotFound {})?;
+        if info.sender != swap.proposer {
+            return Err(ContractError::Unauthorized {});
+        }
+        swaps().remove(deps.storage, id)?;
+
+        let mut resp = Response::new();
+        if !swap.price.is_zero() {
+            resp = resp.add_message(payout::payment_msg(&swap.denom, swap.proposer, swap.price)?);
+        }
+        Ok(resp
+            .add_attribute("action", "cancel_swap")
+            .add_attribute("swap_id", id.to_string())
+        )
+    }
 }
 
 #[cfg_attr(not(feature = "library"), entry_point)]
-pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+pub fn query(deps: Deps, env: Env, msg: QueryMsg) -> StdResult<Binary> {
     match msg {
         QueryMsg::GetMetadata {} => to_binary(&query::get_metadata(deps)?),
-        QueryMsg::GetListed {} => to_binary(&query::get_listed(deps)?),
+        QueryMsg::Config {} => to_binary(&load_config(deps.storage)?),
+        QueryMsg::Roles {} => to_binary(&query::roles(deps)?),
+        QueryMsg::GetListed {
+            start_after,
+            limit,
+            order,
+            min_price,
+            max_price,
+            owner,
+            include_expired,
+        } => to_binary(&query::get_listed(deps, env, start_after, limit, order, min_price, max_price, owner, include_expired)?),
+        QueryMsg::OffersByToken { id, start_after, limit } => to_binary(&query::offers_by_token(deps, id, start_after, limit)?),
+        QueryMsg::OffersByBidder { bidder, start_after, limit } => to_binary(&query::offers_by_bidder(deps, bidder, start_after, limit)?),
+        QueryMsg::CollectionOffers { start_after, limit } => to_binary(&query::collection_offers(deps, start_after, limit)?),
+        QueryMsg::Swap { id } => to_binary(&swaps().load(deps.storage, id)?),
+        QueryMsg::Swaps { proposer, start_after, limit } => to_binary(&query::swaps(deps, proposer, start_after, limit)?),
+        QueryMsg::Auction { id } => to_binary(&AUCTIONS.load(deps.storage, &id)?),
+        QueryMsg::Auctions { start_after, limit } => to_binary(&query::auctions(deps, start_after, limit)?),
+        QueryMsg::DutchPrices { start_after, limit } => to_binary(&query::dutch_prices(deps, env, start_after, limit)?),
+        QueryMsg::SimulateBuy { id } => to_binary(&query::simulate_buy(deps, env, id)?),
+        QueryMsg::GetSales { start_after, limit, token_id } => to_binary(&query::get_sales(deps, start_after, limit, token_id)?),
+        QueryMsg::GetStats {} => to_binary(&query::get_stats(deps)?),
+        QueryMsg::Floor {} => to_binary(&query::floor(deps, env)?),
+        QueryMsg::ListingByToken { id } => to_binary(&query::listing_by_token(deps, env, id)?),
     }
 }
 
 pub mod query {
+    use cosmwasm_std::Uint128;
+
     use super::*;
 
+    #[derive(Debug, Serialize, Deserialize)]
+    struct NftInfoWrapper {
+        pub nft_info: NftInfo
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct NftInfoResponse {
+        pub token_uri: Option<String>
+    }
+
     pub fn get_metadata(deps: Deps) -> StdResult<GetMetadataResponse> {
         let state = STATE.load(deps.storage)?;
         Ok(GetMetadataResponse {
             flagged: state.flagged,
+            flag_reason: state.flag_reason,
+            flag_time: state.flag_time,
             collection: state.collection,
             symbol: state.symbol,
             description: state.description,
@@ -378,10 +1702,274 @@ pub mod query {
         })
     }
 
-    pub fn get_listed(deps: Deps) -> StdResult<Vec<Token>> {
+    pub fn roles(deps: Deps) -> StdResult<RolesResponse> {
+        let ownership = load_ownership(deps.storage)?;
+        Ok(RolesResponse {
+            admin: ownership.admin,
+            pending_admin: ownership.pending_admin,
+            pending_expiry: ownership.pending_expiry,
+            metadata_editor: load_metadata_editor(deps.storage)?,
+            moderators: MODERATORS.may_load(deps.storage)?.unwrap_or_default(),
+        })
+    }
+
+    pub fn get_listed(
+        deps: Deps,
+        env: Env,
+        start_after: Option<ListedCursor>,
+        limit: Option<u32>,
+        order: Option<PriceOrder>,
+        min_price: Option<Uint128>,
+        max_price: Option<Uint128>,
+        owner: Option<String>,
+        include_expired: Option<bool>,
+    ) -> StdResult<GetListedResponse> {
+        let state = STATE.load(deps.storage)?;
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let include_expired = include_expired.unwrap_or(false);
+
+        // the price index is keyed by (price, id), so "" sorts before every id at a given price
+        let cursor = start_after.map(|c| Bound::exclusive((c.price.u128(), c.id)));
+        let lower = min_price.map(|p| Bound::inclusive((p.u128(), String::new())));
+        let upper = max_price
+            .and_then(|p| p.u128().checked_add(1))
+            .map(|p| Bound::exclusive((p, String::new())));
+        let (min, max, order) = match order.unwrap_or(PriceOrder::Ascending) {
+            PriceOrder::Ascending => (cursor.or(lower), upper, Order::Ascending),
+            PriceOrder::Descending => (lower, cursor.or(upper), Order::Descending),
+        };
+
+        // a seller's listings are read from their own index rather than scanning every price
+        let index = listings().idx;
+        let range = match owner {
+            Some(owner) => index.owner.sub_prefix(owner).range(deps.storage, min, max, order),
+            None => index.price.range(deps.storage, min, max, order),
+        };
+        let tokens = range
+            .map(|item| item.map(|(_, token)| token))
+            .filter(|item| match item {
+                Ok(token) => {
+                    min_price.map_or(true, |p| token.price >= p)
+                        && max_price.map_or(true, |p| token.price <= p)
+                        && (include_expired || !token.is_expired(env.block.time))
+                }
+                Err(_) => true,
+            })
+            .take(limit)
+            .collect::<StdResult<Vec<Token>>>()?;
+
+        let listed = tokens
+            .into_iter()
+            .map(|token| {
+                // a burned token has no info, which shouldn't break the whole page
+                let uri = deps.querier
+                    .query_wasm_smart::<NftInfoResponse>(
+                        &state.contract,
+                        &NftInfoWrapper { nft_info: NftInfo { token_id: token.id.clone() }}
+                    )
+                    .ok()
+                    .and_then(|info| info.token_uri)
+                    .unwrap_or_default();
+                NFT {
+                    id: token.id,
+                    uri,
+                    owner: token.owner,
+                    is_listed: token.is_listed,
+                    price: token.price,
+                    expires: token.expires,
+                    denom: token.denom,
+                    reserved_for: token.reserved_for,
+                }
+            })
+            .collect::<Vec<NFT>>();
+
+        Ok(GetListedResponse {
+            number: LISTING_COUNT.may_load(deps.storage)?.unwrap_or_default(),
+            listed,
+        })
+    }
+
+    pub fn offers_by_token(deps: Deps, id: String, start_after: Option<String>, limit: Option<u32>) -> StdResult<OffersResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.as_deref().map(Bound::exclusive);
+
+        let offers = offers()
+            .prefix(&id)
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(_, offer)| offer))
+            .collect::<StdResult<Vec<Offer>>>()?;
+
+        Ok(OffersResponse { offers })
+    }
+
+    pub fn offers_by_bidder(deps: Deps, bidder: String, start_after: Option<String>, limit: Option<u32>) -> StdResult<OffersResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.map(|id| Bound::exclusive((id, bidder.clone())));
+
+        let offers = offers()
+            .idx
+            .bidder
+            .prefix(bidder.clone())
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(_, offer)| offer))
+            .collect::<StdResult<Vec<Offer>>>()?;
+
+        Ok(OffersResponse { offers })
+    }
+
+    pub fn collection_offers(deps: Deps, start_after: Option<u64>, limit: Option<u32>) -> StdResult<CollectionOffersResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.map(Bound::exclusive);
+
+        let offers = crate::state::collection_offers()
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(_, offer)| offer))
+            .collect::<StdResult<Vec<CollectionOffer>>>()?;
+
+        Ok(CollectionOffersResponse { offers })
+    }
+
+    pub fn swaps(deps: Deps, proposer: Option<String>, start_after: Option<u64>, limit: Option<u32>) -> StdResult<SwapsResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.map(Bound::exclusive);
+
+        let swaps = match proposer {
+            Some(proposer) => crate::state::swaps()
+                .idx
+                .proposer
+                .prefix(proposer)
+                .range(deps.storage, start, None, Order::Ascending)
+                .take(limit)
+                .map(|item| item.map(|(_, swap)| swap))
+                .collect::<StdResult<Vec<Swap>>>()?,
+            None => crate::state::swaps()
+                .range(deps.storage, start, None, Order::Ascending)
+                .take(limit)
+                .map(|item| item.map(|(_, swap)| swap))
+                .collect::<StdResult<Vec<Swap>>>()?,
+        };
+
+        Ok(SwapsResponse { swaps })
+    }
+
+    pub fn auctions(deps: Deps, start_after: Option<String>, limit: Option<u32>) -> StdResult<AuctionsResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.as_deref().map(Bound::exclusive);
+
+        let auctions = AUCTIONS
+            .range(deps.storage, start, None, Order::Ascending)
+            .take(limit)
+            .map(|item| item.map(|(_, auction)| auction))
+            .collect::<StdResult<Vec<Auction>>>()?;
+
+        Ok(AuctionsResponse { auctions })
+    }
+
+    pub fn dutch_prices(deps: Deps, env: Env, start_after: Option<String>, limit: Option<u32>) -> StdResult<DutchPricesResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let start = start_after.map(Bound::exclusive);
+
+        let prices = listings()
+            .idx
+            .dutch
+            .prefix(1)
+            .range(deps.storage, start, None, Order::Ascending)
+            .filter(|item| item.as_ref().map_or(true, |(_, token)| !token.is_expired(env.block.time)))
+            .take(limit)
+            .map(|item| item.map(|(id, token)| DutchPrice {
+                id,
+                price: token.current_price(env.block.time),
+            }))
+            .collect::<StdResult<Vec<DutchPrice>>>()?;
+
+        Ok(DutchPricesResponse { prices })
+    }
+
+    pub fn simulate_buy(deps: Deps, env: Env, id: String) -> StdResult<SimulateBuyResponse> {
         let state = STATE.load(deps.storage)?;
-        Ok(state.listed)
+        let token = listings()
+            .may_load(deps.storage, &id)?
+            .ok_or_else(|| StdError::not_found("listing"))?;
+
+        payout::split(token.current_price(env.block.time), token.denom.clone(), &token.owner, &state.royalties, &load_config(deps.storage)?)
     }
 
-}
+    pub fn get_sales(deps: Deps, start_after: Option<u64>, limit: Option<u32>, token_id: Option<String>) -> StdResult<GetSalesResponse> {
+        let limit = limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+        let end = start_after.map(Bound::exclusive);
+
+        let sales = match token_id {
+            Some(token_id) => sales()
+                .idx
+                .token
+                .prefix(token_id)
+                .range(deps.storage, None, end, Order::Descending)
+                .take(limit)
+                .map(|item| item.map(|(_, sale)| sale))
+                .collect::<StdResult<Vec<Sale>>>()?,
+            None => sales()
+                .range(deps.storage, None, end, Order::Descending)
+                .take(limit)
+                .map(|item| item.map(|(_, sale)| sale))
+                .collect::<StdResult<Vec<Sale>>>()?,
+        };
+
+        Ok(GetSalesResponse { sales })
+    }
+
+    pub fn get_stats(deps: Deps) -> StdResult<GetStatsResponse> {
+        let denoms = STATS
+            .range(deps.storage, None, None, Order::Ascending)
+            .map(|item| item.map(|(_, stats)| stats))
+            .collect::<StdResult<Vec<SaleStats>>>()?;
+
+        Ok(GetStatsResponse {
+            sales: denoms.iter().map(|s| s.sales).sum(),
+            denoms,
+        })
+    }
+
+    pub fn floor(deps: Deps, env: Env) -> StdResult<FloorResponse> {
+        let denoms = LISTED_DENOMS
+            .keys(deps.storage, None, None, Order::Ascending)
+            .collect::<StdResult<Vec<String>>>()?;
 
+        let mut floors = vec![];
+        for denom in denoms {
+            // listings come in order of the lowest price they can reach, so once that is
+            // no better than the best live price found, nothing further along can beat it
+            let mut best: Option<FloorPrice> = None;
+            for item in listings().idx.floor.sub_prefix(denom).range(deps.storage, None, None, Order::Ascending) {
+                let (_, token) = item?;
+                if best.as_ref().map_or(false, |b| token.lowest_price() >= b.price) {
+                    break;
+                }
+                if token.is_expired(env.block.time) {
+                    continue;
+                }
+                let price = token.current_price(env.block.time);
+                if best.as_ref().map_or(true, |b| price < b.price) {
+                    best = Some(FloorPrice { denom: token.denom, id: token.id, price });
+                }
+            }
+            floors.extend(best);
+        }
+
+        Ok(FloorResponse { floors })
+    }
+
+    pub fn listing_by_token(deps: Deps, env: Env, id: String) -> StdResult<ListingResponse> {
+        let listing = listings()
+            .may_load(deps.storage, &id)?
+            .ok_or_else(|| StdError::not_found("listing"))?;
+
+        Ok(ListingResponse {
+            current_price: listing.current_price(env.block.time),
+            expired: listing.is_expired(env.block.time),
+            listing,
+        })
+    }
+}
@@ -1,4 +1,4 @@
-use cosmwasm_std::StdError;
+use cosmwasm_std::{StdError, Uint128};
 use thiserror::Error;
 
 #[derive(Error, Debug, PartialEq)]
@@ -6,14 +6,107 @@ pub enum ContractError {
     #[error("{0}")]
     Std(#[from] StdError),
 
+    #[error("Semver parsing error: {0}")]
+    SemVer(String),
+
+    #[error("Cannot migrate from contract {name}")]
+    WrongContract { name: String },
+
+    #[error("Cannot migrate from version {from} to {to}")]
+    CannotDowngrade { from: String, to: String },
+
     #[error("Insufficient funds")]
     InsufficientFunds {},
 
     #[error("Unauthorized")]
     Unauthorized {},
 
+    #[error("No admin transfer is pending")]
+    NoPendingAdmin {},
+
+    #[error("Admin transfer has expired")]
+    AdminProposalExpired {},
+
+    #[error("Collection is flagged, listing and buying are paused")]
+    Flagged {},
+
     #[error("Not found")]
-    NotFound {}
+    NotFound {},
+
+    #[error("Listing expired")]
+    ListingExpired {},
+
+    #[error("Exchange is not approved to transfer this token")]
+    NotApproved {},
+
+    #[error("Listing is stale, the seller no longer owns or has not approved the token")]
+    StaleListing {},
+
+    #[error("Listing is reserved for another buyer")]
+    Reserved {},
+
+    #[error("Listing is still valid")]
+    ListingNotStale {},
+
+    #[error("Offer expired")]
+    OfferExpired {},
+
+    #[error("Swap is stale, the proposer no longer holds or has not approved an offered token")]
+    StaleSwap {},
+
+    #[error("A swap must request distinct tokens and offer distinct tokens or funds")]
+    InvalidSwap {},
+
+    #[error("Quantity must be at least 1")]
+    InvalidQuantity {},
+
+    #[error("No listing can be bought within the sweep's count and budget")]
+    NothingToSweep {},
+
+    #[error("Batches are limited to {max} items")]
+    BatchTooLarge { max: u32 },
+
+    #[error("Auction has ended")]
+    AuctionEnded {},
+
+    #[error("Auction has not ended yet")]
+    AuctionNotEnded {},
+
+    #[error("Auction already has bids")]
+    AuctionHasBids {},
+
+    #[error("Minimum bid increment must be above zero")]
+    InvalidIncrement {},
+
+    #[error("Bid too low, minimum is {min}")]
+    BidTooLow { min: Uint128 },
+
+    #[error("Listing is not priced in this denom")]
+    InvalidDenom {},
+
+    #[error("Denom {denom} is not accepted")]
+    DenomNotAccepted { denom: String },
+
+    #[error("Platform fee cannot exceed {max} basis points")]
+    FeeTooHigh { max: u16 },
+
+    #[error("Fee recipient shares must add up to 10000 basis points")]
+    InvalidFeeRecipients {},
+
+    #[error("Royalty cannot exceed {max} basis points")]
+    RoyaltyTooHigh { max: u16 },
+
+    #[error("Creator shares must add up to 10000 basis points")]
+    InvalidCreatorShares {},
+
+    #[error("Dutch auction must decline to a lower end price over a non-empty window")]
+    InvalidDutchAuction {},
     // Add any other custom errors you like here.
     // Look at https://docs.rs/thiserror/1.0.21/thiserror/ for details.
-}
\ No newline at end of file
+}
+
+impl From<semver::Error> for ContractError {
+    fn from(err: semver::Error) -> Self {
+        Self::SemVer(err.to_string())
+    }
+}
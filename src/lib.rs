@@ -3,6 +3,7 @@ pub mod contract;
 mod error;
 pub mod helpers;
 pub mod msg;
+pub mod payout;
 pub mod state;
 
 #[cfg(test)]
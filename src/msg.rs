@@ -1,9 +1,12 @@
 use cosmwasm_schema::{cw_serde, QueryResponses};
-use cosmwasm_std::Uint128;
+use cosmwasm_std::{Binary, Uint128};
+use cw_utils::Expiration;
 
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+use crate::state::{Auction, CollectionOffer, Config, Offer, Sale, SaleStats, Swap, Token};
+
 #[cw_serde]
 pub struct InstantiateMsg {
     pub collection: String,
@@ -15,6 +18,23 @@ pub struct InstantiateMsg {
     pub supply: i32,
     pub creators: Vec<Creator>,
     pub basis_points: u32, // 100 basis points = 1% of list price
+    pub fee_basis_points: Option<u16>, // platform fee, 3% if not set
+    pub fee_recipients: Option<Vec<FeeRecipient>>,
+    pub max_royalty_basis_points: Option<u16>, // 10% if not set
+}
+
+/// Only read when migrating from 0.0.1, to use instead of its royalties
+#[cw_serde]
+pub struct MigrateMsg {
+    pub creators: Option<Vec<Creator>>,
+    pub basis_points: Option<u16>,
+}
+
+#[cw_serde]
+#[derive(Eq)]
+pub struct FeeRecipient {
+    pub address: String,
+    pub share: u16, // basis points of the platform fee, all shares add up to 10000
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
@@ -28,6 +48,18 @@ pub struct OwnerOf {
     pub token_id: String,
 } 
 
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct ApprovedForAll {
+    pub owner: String,
+    pub start_after: Option<String>,
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct NftInfo {
+    pub token_id: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct GetApprovals {
     token_id: String
@@ -46,13 +78,62 @@ pub struct MintingInfo {
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct Creator {
     pub address: String,
-    pub share: i8
+    pub share: u16 // basis points of the royalty, all shares add up to 10000
 }
 
 #[cw_serde]
 pub enum ExecuteMsg {
-    Flag { enabled: bool },
+    /// Admin and moderators only
+    Flag {
+        enabled: bool,
+        reason: Option<String>
+    },
+    /// Admin only
+    UpdateConfig {
+        accepted_denoms: Option<Vec<String>>,
+        fee_basis_points: Option<u16>,
+        fee_recipients: Option<Vec<FeeRecipient>>,
+        max_royalty_basis_points: Option<u16>
+    },
+    /// Admin only. `new_admin` takes over once they accept, if they do before `expiry`
+    ProposeAdmin {
+        new_admin: String,
+        expiry: Option<Expiration>
+    },
+    /// Pending admin only
+    AcceptAdmin {},
+    /// Admin only. Leaves the exchange without an admin for good
+    RenounceAdmin {},
+    /// Admin only
+    SetMetadataEditor {
+        editor: String
+    },
+    /// Admin only
+    UpdateModerators {
+        add: Vec<String>,
+        remove: Vec<String>
+    },
     List {
+        id: String,
+        price: Uint128,
+        expires: i128,
+        denom: Option<Denom>, // INJ if not set
+        reserved_for: Option<String> // only this address can buy, if set
+    },
+    /// List at a price that falls over time. `price` is the start price.
+    ListDutch {
+        id: String,
+        price: Uint128,
+        dutch: DutchAuction,
+        expires: i128,
+        denom: Option<Denom>
+    },
+    /// Lists up to 100 tokens at once, all or nothing
+    BatchList {
+        items: Vec<ListItem>
+    },
+    /// Changes the price and expiry of a listing, keeping its kind and denom
+    UpdatePrice {
         id: String,
         price: Uint128,
         expires: i128
@@ -60,9 +141,93 @@ pub enum ExecuteMsg {
     Buy {
         id : String 
     },
+    /// Buys up to `max_count` of the cheapest live listings in the attached denom, spending
+    /// at most `max_total_price`, from `ids` only if given. Unspent funds are refunded.
+    Sweep {
+        max_count: u32,
+        max_total_price: Uint128,
+        ids: Option<Vec<String>>
+    },
     DeList {
         id: String
     },
+    /// Delists up to 100 tokens at once, all or nothing
+    BatchDeList {
+        ids: Vec<String>
+    },
+    /// Removes up to `limit` expired listings. Callable by anyone.
+    PurgeExpired {
+        limit: Option<u32>
+    },
+    /// Removes a listing whose seller no longer owns or has not approved the token. Callable by anyone.
+    InvalidateListing {
+        id: String
+    },
+    /// Moves up to `limit` of the listings left over from a 0.0.1 migration. Callable by anyone.
+    MigrateListings {
+        limit: Option<u32>
+    },
+    /// cw721 `send_nft` hook, used to list a token in escrow
+    ReceiveNft(Cw721ReceiveMsg),
+    /// cw20 `send` hook, used to buy a listing priced in that cw20
+    Receive(Cw20ReceiveMsg),
+    /// Offer the attached INJ for a token. The funds are held until the offer is accepted or cancelled.
+    MakeOffer {
+        id: String,
+        expires: i128
+    },
+    CancelOffer {
+        id: String
+    },
+    /// Sell a token to one of its bidders. Only the current owner can accept.
+    AcceptOffer {
+        id: String,
+        bidder: String
+    },
+    /// Bid `price` each for up to `quantity` tokens of the collection. `price * quantity` INJ must be attached.
+    MakeCollectionOffer {
+        price: Uint128,
+        quantity: u32,
+        expires: i128
+    },
+    /// Refunds whatever part of the offer is still unfilled
+    CancelCollectionOffer {
+        offer_id: u64
+    },
+    /// Sell any token you own into a collection offer
+    AcceptCollectionOffer {
+        offer_id: u64,
+        token_id: String
+    },
+    /// Bid the attached INJ on an auction. The previous best bid is refunded.
+    PlaceBid {
+        id: String
+    },
+    /// Ends an auction past its end time, paying the seller or returning the token. Callable by anyone.
+    SettleAuction {
+        id: String
+    },
+    /// Seller can pull an auction that has no bids yet
+    CancelAuction {
+        id: String
+    },
+    /// Offer `offered` tokens of any cw721, plus any native funds attached, for `requested`
+    /// tokens of this collection. The exchange must be approved for every offered token.
+    ProposeSwap {
+        offered: Vec<SwapItem>,
+        requested: Vec<String>,
+        expires: i128
+    },
+    /// Holder of every requested token only. Their tokens must be approved to the exchange or
+    /// escrowed in their listings.
+    AcceptSwap {
+        id: u64
+    },
+    /// Proposer only. Refunds the funds
+    CancelSwap {
+        id: u64
+    },
+    /// Metadata editor only
     UpdateMetadata {
         creators: Option<Vec<Creator>>,
         collection: Option<String>,
@@ -78,6 +243,115 @@ pub enum ExecuteMsg {
     }
 }
 
+#[cw_serde]
+pub struct Cw721ReceiveMsg {
+    pub sender: String,
+    pub token_id: String,
+    pub msg: Binary,
+}
+
+/// A token of any cw721 contract, one side of a swap
+#[cw_serde]
+#[derive(Eq)]
+pub struct SwapItem {
+    pub contract: String,
+    pub token_id: String,
+}
+
+#[cw_serde]
+pub struct ListItem {
+    pub id: String,
+    pub price: Uint128,
+    pub expires: i128,
+    pub denom: Option<Denom>, // INJ if not set
+    pub reserved_for: Option<String>
+}
+
+/// Embedded in the `msg` of a cw721 `send_nft` to the exchange
+#[cw_serde]
+pub enum ReceiveNftMsg {
+    List {
+        price: Uint128,
+        expires: i128,
+        denom: Option<Denom>
+    },
+    ListDutch {
+        price: Uint128,
+        dutch: DutchAuction,
+        expires: i128,
+        denom: Option<Denom>
+    },
+    Auction {
+        reserve_price: Uint128,
+        min_increment: Uint128,
+        end_time: u64
+    },
+}
+
+/// What a listing is priced in
+#[cw_serde]
+#[derive(Eq)]
+pub enum Denom {
+    Native(String),
+    Cw20(String), // token contract address
+}
+
+impl Default for Denom {
+    fn default() -> Self {
+        Denom::Native("inj".to_string())
+    }
+}
+
+/// How a denom shows up in event attributes: the bank denom, or `cw20:` and the token address
+impl std::fmt::Display for Denom {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Denom::Native(denom) => write!(f, "{}", denom),
+            Denom::Cw20(contract) => write!(f, "cw20:{}", contract),
+        }
+    }
+}
+
+#[cw_serde]
+pub struct Cw20ReceiveMsg {
+    pub sender: String,
+    pub amount: Uint128,
+    pub msg: Binary,
+}
+
+/// Embedded in the `msg` of a cw20 `send` to the exchange
+#[cw_serde]
+pub enum Cw20HookMsg {
+    Buy {
+        id: String
+    },
+}
+
+#[cw_serde]
+pub enum Cw20ExecuteMsg {
+    Transfer {
+        recipient: String,
+        amount: Uint128
+    },
+}
+
+/// Price falls from the listing price at `start_time` to `end_price` at `end_time`
+#[cw_serde]
+#[derive(Eq)]
+pub struct DutchAuction {
+    pub end_price: Uint128,
+    pub start_time: u64,
+    pub end_time: u64,
+    pub decay: Decay,
+}
+
+#[cw_serde]
+#[derive(Eq)]
+pub enum Decay {
+    Linear,
+    Exponential,
+}
+
 #[derive(Serialize, Deserialize, Clone, PartialEq, Eq, JsonSchema)]
 pub struct Tmessage {
     pub transfer_nft: SendTokenMsg
@@ -106,14 +380,119 @@ pub enum QueryMsg {
     #[returns(GetMetadataResponse)]
     GetMetadata {},
 
+    #[returns(Config)]
+    Config {},
+
+    #[returns(RolesResponse)]
+    Roles {},
+
     #[returns(GetListedResponse)]
-    GetListed {},
+    GetListed {
+        /// cursor from the last item of the previous page
+        start_after: Option<ListedCursor>,
+        limit: Option<u32>,
+        order: Option<PriceOrder>,
+        min_price: Option<Uint128>,
+        max_price: Option<Uint128>,
+        owner: Option<String>,
+        include_expired: Option<bool>,
+    },
+
+    #[returns(OffersResponse)]
+    OffersByToken {
+        id: String,
+        start_after: Option<String>, // bidder
+        limit: Option<u32>,
+    },
+
+    #[returns(OffersResponse)]
+    OffersByBidder {
+        bidder: String,
+        start_after: Option<String>, // token id
+        limit: Option<u32>,
+    },
+
+    #[returns(CollectionOffersResponse)]
+    CollectionOffers {
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    #[returns(Swap)]
+    Swap {
+        id: u64,
+    },
+
+    #[returns(SwapsResponse)]
+    Swaps {
+        proposer: Option<String>,
+        start_after: Option<u64>,
+        limit: Option<u32>,
+    },
+
+    #[returns(Auction)]
+    Auction {
+        id: String,
+    },
+
+    #[returns(AuctionsResponse)]
+    Auctions {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    /// Live price of every unexpired dutch listing, by token id
+    #[returns(DutchPricesResponse)]
+    DutchPrices {
+        start_after: Option<String>,
+        limit: Option<u32>,
+    },
+
+    /// What buying a listing right now costs and where the money goes
+    #[returns(SimulateBuyResponse)]
+    SimulateBuy {
+        id: String,
+    },
+
+    /// Completed sales, newest first
+    #[returns(GetSalesResponse)]
+    GetSales {
+        start_after: Option<u64>, // sale id
+        limit: Option<u32>,
+        token_id: Option<String>,
+    },
+
+    #[returns(GetStatsResponse)]
+    GetStats {},
+
+    /// Cheapest unexpired listing in each denom, at its current price
+    #[returns(FloorResponse)]
+    Floor {},
+
+    #[returns(ListingResponse)]
+    ListingByToken {
+        id: String,
+    },
+}
+
+#[cw_serde]
+pub struct ListedCursor {
+    pub price: Uint128,
+    pub id: String,
+}
+
+#[cw_serde]
+pub enum PriceOrder {
+    Ascending,
+    Descending,
 }
 
 // We define a custom struct for each query response
 #[cw_serde]
 pub struct GetMetadataResponse {
     pub flagged: bool,
+    pub flag_reason: Option<String>,
+    pub flag_time: Option<u64>,
     pub collection: String,
     pub description: String,
     pub symbol: String,
@@ -128,16 +507,106 @@ pub struct GetMetadataResponse {
     pub discord: String,
 }
 
+#[cw_serde]
+pub struct RolesResponse {
+    pub admin: Option<String>,
+    pub pending_admin: Option<String>,
+    pub pending_expiry: Option<Expiration>,
+    pub metadata_editor: String,
+    pub moderators: Vec<String>
+}
+
 #[cw_serde]
 pub struct GetListedResponse {
-    pub number: i32,
+    pub number: u32, // all listings, ignoring the filters and paging
     pub listed: Vec<NFT>
 }
 
+#[cw_serde]
+pub struct OffersResponse {
+    pub offers: Vec<Offer>
+}
+
+#[cw_serde]
+pub struct CollectionOffersResponse {
+    pub offers: Vec<CollectionOffer>
+}
+
+#[cw_serde]
+pub struct SwapsResponse {
+    pub swaps: Vec<Swap>
+}
+
+#[cw_serde]
+pub struct AuctionsResponse {
+    pub auctions: Vec<Auction>
+}
+
+#[cw_serde]
+pub struct DutchPricesResponse {
+    pub prices: Vec<DutchPrice>
+}
+
+#[cw_serde]
+pub struct DutchPrice {
+    pub id: String,
+    pub price: Uint128
+}
+
+#[cw_serde]
+pub struct Payment {
+    pub address: String,
+    pub amount: Uint128
+}
+
+#[cw_serde]
+pub struct FloorResponse {
+    pub floors: Vec<FloorPrice>
+}
+
+#[cw_serde]
+pub struct FloorPrice {
+    pub denom: Denom,
+    pub id: String,
+    pub price: Uint128
+}
+
+#[cw_serde]
+pub struct ListingResponse {
+    pub listing: Token,
+    pub current_price: Uint128,
+    pub expired: bool
+}
+
+#[cw_serde]
+pub struct GetSalesResponse {
+    pub sales: Vec<Sale>
+}
+
+#[cw_serde]
+pub struct GetStatsResponse {
+    pub sales: u64, // across all denoms
+    pub denoms: Vec<SaleStats>
+}
+
+/// Breakdown of a sale, every amount adds up to `price`
+#[cw_serde]
+pub struct SimulateBuyResponse {
+    pub price: Uint128,
+    pub denom: Denom,
+    pub royalties: Vec<Payment>,
+    pub fees: Vec<Payment>,
+    pub seller: Payment
+}
+
 #[cw_serde]
 pub struct NFT {
     pub id: String,
     pub uri: String,
     pub owner: String,
-    pub is_listed: bool
+    pub is_listed: bool,
+    pub price: Uint128,
+    pub expires: i128,
+    pub denom: Denom,
+    pub reserved_for: Option<String> // a private listing, only this address can buy
 }
\ No newline at end of file
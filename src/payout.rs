@@ -0,0 +1,111 @@
+use std::collections::BTreeMap;
+
+use cosmwasm_std::{attr, coins, to_binary, Attribute, BankMsg, CosmosMsg, StdError, StdResult, Uint128, WasmMsg};
+
+use crate::msg::{Cw20ExecuteMsg, Denom, Payment, Royalties, SimulateBuyResponse};
+use crate::state::Config;
+
+/// Splits a sale at `price` between the creators, the platform and the seller.
+///
+/// The buyer pays exactly `price`. Royalty and platform fee are taken out of it and the seller
+/// gets the rest, so every payment in the breakdown adds up to `price`. Rounding dust within
+/// the royalty or the fee goes to the first recipient of that split.
+pub fn split(price: Uint128, denom: Denom, seller: &str, royalties: &Royalties, config: &Config) -> StdResult<SimulateBuyResponse> {
+    let royalty_total = price.multiply_ratio(royalties.seller_fee_basis_points as u128, 10_000u128);
+    let fee_total = price.multiply_ratio(config.fee_basis_points as u128, 10_000u128);
+    let seller_amount = price
+        .checked_sub(royalty_total + fee_total)
+        .map_err(|_| StdError::generic_err("royalty and platform fee exceed the price"))?;
+
+    let creators: Vec<(String, u128)> = royalties.creators
+        .iter()
+        .map(|c| (c.address.clone(), c.share as u128))
+        .collect();
+    let fee_recipients: Vec<(String, u128)> = config.fee_recipients
+        .iter()
+        .map(|r| (r.address.clone(), r.share as u128))
+        .collect();
+
+    let royalties = shares(royalty_total, &creators);
+    // nobody to pay the royalty to, so it stays with the seller
+    let seller_amount = if royalties.is_empty() { seller_amount + royalty_total } else { seller_amount };
+
+    Ok(SimulateBuyResponse {
+        price,
+        denom,
+        royalties,
+        fees: shares(fee_total, &fee_recipients),
+        seller: Payment { address: seller.to_string(), amount: seller_amount },
+    })
+}
+
+/// Divides `total` by weight. Each recipient gets the floor of their share and the first one
+/// also gets whatever is left over.
+fn shares(total: Uint128, recipients: &[(String, u128)]) -> Vec<Payment> {
+    let weight: u128 = recipients.iter().map(|(_, w)| w).sum();
+    if weight == 0 {
+        return vec![];
+    }
+
+    let mut payments: Vec<Payment> = recipients
+        .iter()
+        .map(|(address, w)| Payment { address: address.clone(), amount: total.multiply_ratio(*w, weight) })
+        .collect();
+    let paid: Uint128 = payments.iter().map(|p| p.amount).sum();
+    payments[0].amount += total - paid;
+    payments
+}
+
+/// Sends `amount` of `denom`, as a bank send or a cw20 transfer
+pub fn payment_msg(denom: &Denom, to_address: String, amount: Uint128) -> StdResult<CosmosMsg> {
+    Ok(match denom {
+        Denom::Native(denom) => BankMsg::Send { to_address, amount: coins(amount.u128(), denom) }.into(),
+        Denom::Cw20(contract) => WasmMsg::Execute {
+            contract_addr: contract.into(),
+            msg: to_binary(&Cw20ExecuteMsg::Transfer { recipient: to_address, amount })?,
+            funds: vec![],
+        }.into(),
+    })
+}
+
+/// One payment per creator, fee recipient and the seller, skipping empty ones
+pub fn messages(breakdown: &SimulateBuyResponse) -> StdResult<Vec<CosmosMsg>> {
+    breakdown.royalties
+        .iter()
+        .chain(breakdown.fees.iter())
+        .chain(std::iter::once(&breakdown.seller))
+        .filter(|p| !p.amount.is_zero())
+        .map(|p| payment_msg(&breakdown.denom, p.address.clone(), p.amount))
+        .collect()
+}
+
+/// Adds up what every recipient is owed across several sales in the same denom, so each
+/// is paid once
+pub fn totals(breakdowns: &[SimulateBuyResponse]) -> Vec<Payment> {
+    let mut totals: BTreeMap<&str, Uint128> = BTreeMap::new();
+    for p in breakdowns
+        .iter()
+        .flat_map(|b| b.royalties.iter().chain(b.fees.iter()).chain(std::iter::once(&b.seller)))
+    {
+        *totals.entry(&p.address).or_default() += p.amount;
+    }
+    totals
+        .into_iter()
+        .filter(|(_, amount)| !amount.is_zero())
+        .map(|(address, amount)| Payment { address: address.to_string(), amount })
+        .collect()
+}
+
+/// Sale attributes shared by every event that moves a token for payment
+pub fn attributes(breakdown: &SimulateBuyResponse) -> Vec<Attribute> {
+    let royalty: Uint128 = breakdown.royalties.iter().map(|p| p.amount).sum();
+    let fee: Uint128 = breakdown.fees.iter().map(|p| p.amount).sum();
+    vec![
+        attr("seller", &breakdown.seller.address),
+        attr("price", breakdown.price),
+        attr("denom", breakdown.denom.to_string()),
+        attr("royalty", royalty),
+        attr("fee", fee),
+        attr("seller_amount", breakdown.seller.amount),
+    ]
+}
@@ -1,13 +1,18 @@
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use cosmwasm_std::Uint128;
-use crate::msg::Royalties;
-use cw_storage_plus::Item;
+use cosmwasm_std::{Decimal, StdResult, Storage, Timestamp, Uint128};
+use crate::msg::{Decay, Denom, DutchAuction, FeeRecipient, Royalties, SwapItem};
+use cw_storage_plus::{Index, IndexList, IndexedMap, Item, Map, MultiIndex};
+use cw_utils::Expiration;
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
 pub struct State {
-    pub flagged: bool,
+    pub flagged: bool, // pauses listing and buying
+    #[serde(default)]
+    pub flag_reason: Option<String>,
+    #[serde(default)]
+    pub flag_time: Option<u64>, // block time of the last flag change, in seconds
     pub collection: String,
     pub contract: String,
     pub description: String,
@@ -15,9 +20,8 @@ pub struct State {
     pub logo_uri: String,
     pub banner_uri: String,
     pub supply: i32,
-    pub owner: String,
+    pub owner: String, // collection creator, the initial admin and metadata editor
     pub royalties: Royalties,
-    pub listed: Vec<Token>, 
     pub contact: String,
     pub discord: String,
     pub twitter: String,
@@ -25,13 +29,491 @@ pub struct State {
     pub website: String
 }
 
+/// Listings, offers and swaps expire once the block time reaches `expires` (in seconds).
+/// 0 never expires.
+pub fn is_expired(expires: i128, now: Timestamp) -> bool {
+    expires > 0 && expires <= now.seconds() as i128
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
 pub struct Token {
     pub id: String,
     pub owner: String,
     pub is_listed: bool,
     pub price: Uint128, // 0 if unlisted
-    pub expires: i128, // 0 if unlisted
+    pub expires: i128, // 0 if unlisted or never expires
+    #[serde(default)]
+    pub escrowed: bool, // true if the exchange holds the NFT
+    #[serde(default)]
+    pub kind: ListingKind,
+    #[serde(default)]
+    pub denom: Denom,
+    #[serde(default)]
+    pub reserved_for: Option<String>, // only this address can buy, if set
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum ListingKind {
+    #[default]
+    Fixed,
+    Dutch(DutchAuction), // `price` is the start price
+}
+
+impl Token {
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        is_expired(self.expires, now)
+    }
+
+    /// Price a buyer pays at `now`.
+    pub fn current_price(&self, now: Timestamp) -> Uint128 {
+        match &self.kind {
+            ListingKind::Fixed => self.price,
+            ListingKind::Dutch(dutch) => dutch_price(self.price, dutch, now.seconds()),
+        }
+    }
+
+    /// The least this listing can ever cost, where a dutch listing bottoms out.
+    pub fn lowest_price(&self) -> Uint128 {
+        match &self.kind {
+            ListingKind::Fixed => self.price,
+            ListingKind::Dutch(dutch) => dutch.end_price,
+        }
+    }
+
+    /// True if the listing is reserved for someone other than `buyer`
+    pub fn reserved_for_other(&self, buyer: &str) -> bool {
+        self.reserved_for.as_ref().map_or(false, |r| r != buyer)
+    }
+}
+
+fn dutch_price(start_price: Uint128, dutch: &DutchAuction, now: u64) -> Uint128 {
+    if now <= dutch.start_time {
+        return start_price;
+    }
+    if now >= dutch.end_time {
+        return dutch.end_price;
+    }
+    let elapsed = now - dutch.start_time;
+    let duration = dutch.end_time - dutch.start_time;
+
+    match dutch.decay {
+        Decay::Linear => start_price - (start_price - dutch.end_price).multiply_ratio(elapsed, duration),
+        Decay::Exponential => {
+            // start * (end / start) ^ (elapsed / duration), with the exponent taken to 32 binary
+            // digits: each digit multiplies in the matching repeated square root of the ratio
+            let ratio = Decimal::from_ratio(dutch.end_price, start_price);
+            let bits = ((elapsed as u128) << 32) / duration as u128;
+            let mut root = ratio;
+            let mut factor = Decimal::one();
+            for i in (0..32).rev() {
+                root = root.sqrt();
+                if bits & (1 << i) != 0 {
+                    factor *= root;
+                }
+            }
+            (start_price * factor).max(dutch.end_price)
+        }
+    }
+}
+
+/// Exchange settings managed by the collection owner
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq, JsonSchema)]
+pub struct Config {
+    pub accepted_denoms: Vec<String>, // native denoms listings can be priced in
+    pub fee_basis_points: u16, // platform fee, 100 basis points = 1%
+    pub fee_recipients: Vec<FeeRecipient>,
+    #[serde(default = "default_max_royalty_basis_points")]
+    pub max_royalty_basis_points: u16, // cap on the collection royalty
+}
+
+fn default_max_royalty_basis_points() -> u16 {
+    1_000
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            accepted_denoms: vec!["inj".to_string()],
+            fee_basis_points: 300,
+            fee_recipients: vec![FeeRecipient {
+                address: "inj1f4psdn7c7ap3aruu5zpex5p9a05k8qd077736v".to_string(),
+                share: 10_000,
+            }],
+            max_royalty_basis_points: default_max_royalty_basis_points(),
+        }
+    }
+}
+
+pub const STATE: Item<State> = Item::new("state");
+pub const CONFIG: Item<Config> = Item::new("config");
+
+/// Exchange admin, handed over in two steps like cw-ownable
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema)]
+pub struct Ownership {
+    pub admin: Option<String>, // None once renounced
+    pub pending_admin: Option<String>,
+    pub pending_expiry: Option<Expiration>,
+}
+
+pub const OWNERSHIP: Item<Ownership> = Item::new("ownership");
+/// Who may update the collection metadata and royalties
+pub const METADATA_EDITOR: Item<String> = Item::new("metadata_editor");
+/// Besides the admin, who may flag the collection
+pub const MODERATORS: Item<Vec<String>> = Item::new("moderators");
+
+/// Exchanges instantiated before the admin role existed are run by `State.owner`
+pub fn load_ownership(storage: &dyn Storage) -> StdResult<Ownership> {
+    match OWNERSHIP.may_load(storage)? {
+        Some(ownership) => Ok(ownership),
+        None => Ok(Ownership {
+            admin: Some(STATE.load(storage)?.owner),
+            pending_admin: None,
+            pending_expiry: None,
+        }),
+    }
+}
+
+/// Falls back to `State.owner`, the collection creator, like `load_ownership`
+pub fn load_metadata_editor(storage: &dyn Storage) -> StdResult<String> {
+    match METADATA_EDITOR.may_load(storage)? {
+        Some(editor) => Ok(editor),
+        None => Ok(STATE.load(storage)?.owner),
+    }
+}
+
+/// Exchanges instantiated before the config existed run on the defaults
+pub fn load_config(storage: &dyn Storage) -> StdResult<Config> {
+    Ok(CONFIG.may_load(storage)?.unwrap_or_default())
+}
+pub const LISTING_COUNT: Item<u32> = Item::new("listing_count");
+/// Number of listings per denom, keyed by the denom as it appears in events
+pub const LISTED_DENOMS: Map<&str, u32> = Map::new("listed_denoms");
+pub const COLLECTION_OFFER_SEQ: Item<u64> = Item::new("collection_offer_seq");
+
+/// Secondary indexes over the listings map, so the order book can be walked
+/// by price or by seller without loading every listing.
+pub struct ListingIndexes<'a> {
+    pub price: MultiIndex<'a, u128, Token, String>,
+    pub owner: MultiIndex<'a, (String, u128), Token, String>,
+    pub expires: MultiIndex<'a, i128, Token, String>,
+    pub dutch: MultiIndex<'a, u8, Token, String>,
+    pub floor: MultiIndex<'a, (String, u128), Token, String>,
+}
+
+impl<'a> IndexList<Token> for ListingIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Token>> + '_> {
+        let v: Vec<&dyn Index<Token>> = vec![&self.price, &self.owner, &self.expires, &self.dutch, &self.floor];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Listed tokens, keyed by token id.
+pub fn listings<'a>() -> IndexedMap<'a, &'a str, Token, ListingIndexes<'a>> {
+    let indexes = ListingIndexes {
+        price: MultiIndex::new(|_pk, t| t.price.u128(), "listings", "listings__price"),
+        // by seller, then by price, so one seller's listings page like the price index
+        owner: MultiIndex::new(|_pk, t| (t.owner.clone(), t.price.u128()), "listings", "listings__owner"),
+        expires: MultiIndex::new(|_pk, t| t.expires, "listings", "listings__expires"),
+        dutch: MultiIndex::new(|_pk, t| matches!(t.kind, ListingKind::Dutch(_)) as u8, "listings", "listings__dutch"),
+        // by denom, then by the lowest price the listing can reach. Private listings sit under
+        // their own prefix, so walks over a denom never see them
+        floor: MultiIndex::new(
+            |_pk, t| {
+                let denom = match t.reserved_for {
+                    Some(_) => format!("reserved:{}", t.denom),
+                    None => t.denom.to_string(),
+                };
+                (denom, t.lowest_price().u128())
+            },
+            "listings",
+            "listings__floor",
+        ),
+    };
+    IndexedMap::new("listings", indexes)
+}
+
+/// A creator as stored before 0.1.0, with `share` in whole percents adding up to 100
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct LegacyCreator {
+    pub address: String,
+    pub share: i8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct LegacyRoyalties {
+    pub seller_fee_basis_points: u32,
+    pub creators: Vec<LegacyCreator>,
+}
+
+/// `State` as stored before 0.1.0, when every listing lived in its `listed` vector
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct LegacyState {
+    pub flagged: bool,
+    pub collection: String,
+    pub contract: String,
+    pub description: String,
+    pub symbol: String,
+    pub logo_uri: String,
+    pub banner_uri: String,
+    pub supply: i32,
+    pub owner: String,
+    pub royalties: LegacyRoyalties,
+    #[serde(default)]
+    pub listed: Vec<Token>,
+    pub contact: String,
+    pub discord: String,
+    pub twitter: String,
+    pub telegram: String,
+    pub website: String
+}
+
+/// The same key as `STATE`, only readable before the 0.1.0 migration
+pub const LEGACY_STATE: Item<LegacyState> = Item::new("state");
+/// Legacy listings still waiting to be moved into `listings()`
+pub const LEGACY_LISTED: Item<Vec<Token>> = Item::new("legacy_listed");
+
+/// Moves up to `limit` waiting legacy listings into `listings()`, returning the ones that were
+/// moved and how many are left. Expired listings are kept, they can be purged like any other.
+pub fn migrate_legacy_listings(storage: &mut dyn Storage, limit: usize) -> StdResult<(Vec<Token>, usize)> {
+    let mut waiting = LEGACY_LISTED.may_load(storage)?.unwrap_or_default();
+    let rest = waiting.split_off(limit.min(waiting.len()));
+    let mut migrated = vec![];
+    for token in waiting {
+        // a token relisted since then keeps its new listing
+        if !listings().has(storage, &token.id) {
+            add_listing(storage, &token)?;
+            migrated.push(token);
+        }
+    }
+    if rest.is_empty() {
+        LEGACY_LISTED.remove(storage);
+    } else {
+        LEGACY_LISTED.save(storage, &rest)?;
+    }
+    Ok((migrated, rest.len()))
 }
 
-pub const STATE: Item<State> = Item::new("state");
\ No newline at end of file
+/// Saves a new listing and bumps the listing counts.
+pub fn add_listing(storage: &mut dyn Storage, token: &Token) -> StdResult<()> {
+    listings().save(storage, &token.id, token)?;
+    let count = LISTING_COUNT.may_load(storage)?.unwrap_or_default();
+    LISTING_COUNT.save(storage, &(count + 1))?;
+    let denom = token.denom.to_string();
+    let count = LISTED_DENOMS.may_load(storage, &denom)?.unwrap_or_default();
+    LISTED_DENOMS.save(storage, &denom, &(count + 1))
+}
+
+/// Removes a listing and decrements the listing counts.
+pub fn remove_listing(storage: &mut dyn Storage, id: &str) -> StdResult<()> {
+    let token = match listings().may_load(storage, id)? {
+        Some(token) => token,
+        None => return Ok(()),
+    };
+    listings().remove(storage, id)?;
+    let count = LISTING_COUNT.may_load(storage)?.unwrap_or_default();
+    LISTING_COUNT.save(storage, &count.saturating_sub(1))?;
+    let denom = token.denom.to_string();
+    match LISTED_DENOMS.may_load(storage, &denom)?.unwrap_or_default() {
+        0 | 1 => LISTED_DENOMS.remove(storage, &denom),
+        count => LISTED_DENOMS.save(storage, &denom, &(count - 1))?,
+    }
+    Ok(())
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct Offer {
+    pub token_id: String,
+    pub bidder: String,
+    pub price: Uint128, // escrowed in the exchange
+    pub expires: i128, // 0 never expires
+}
+
+impl Offer {
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        is_expired(self.expires, now)
+    }
+}
+
+pub struct OfferIndexes<'a> {
+    pub bidder: MultiIndex<'a, String, Offer, (String, String)>,
+}
+
+impl<'a> IndexList<Offer> for OfferIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Offer>> + '_> {
+        let v: Vec<&dyn Index<Offer>> = vec![&self.bidder];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Offers on individual tokens, keyed by (token id, bidder).
+pub fn offers<'a>() -> IndexedMap<'a, (&'a str, &'a str), Offer, OfferIndexes<'a>> {
+    let indexes = OfferIndexes {
+        bidder: MultiIndex::new(|_pk, o| o.bidder.clone(), "offers", "offers__bidder"),
+    };
+    IndexedMap::new("offers", indexes)
+}
+
+/// A bid on any `quantity` tokens of the collection at `price` each.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct CollectionOffer {
+    pub id: u64,
+    pub collection: String,
+    pub bidder: String,
+    pub price: Uint128, // per token
+    pub quantity: u32, // tokens still wanted, price * quantity is escrowed
+    pub expires: i128, // 0 never expires
+}
+
+impl CollectionOffer {
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        is_expired(self.expires, now)
+    }
+}
+
+pub struct CollectionOfferIndexes<'a> {
+    pub bidder: MultiIndex<'a, String, CollectionOffer, u64>,
+}
+
+impl<'a> IndexList<CollectionOffer> for CollectionOfferIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<CollectionOffer>> + '_> {
+        let v: Vec<&dyn Index<CollectionOffer>> = vec![&self.bidder];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Collection offers, keyed by a sequential id.
+pub fn collection_offers<'a>() -> IndexedMap<'a, u64, CollectionOffer, CollectionOfferIndexes<'a>> {
+    let indexes = CollectionOfferIndexes {
+        bidder: MultiIndex::new(|_pk, o| o.bidder.clone(), "collection_offers", "collection_offers__bidder"),
+    };
+    IndexedMap::new("collection_offers", indexes)
+}
+
+/// Tokens of any cw721, plus optional funds, offered for specific tokens of this collection.
+/// Only the funds are escrowed, the proposer's tokens are checked again when it's accepted.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct Swap {
+    pub id: u64,
+    pub proposer: String,
+    pub offered: Vec<SwapItem>,
+    pub requested: Vec<String>, // token ids in this collection
+    pub price: Uint128, // escrowed cash leg, 0 if none
+    pub denom: Denom,
+    pub expires: i128, // 0 never expires
+}
+
+impl Swap {
+    pub fn is_expired(&self, now: Timestamp) -> bool {
+        is_expired(self.expires, now)
+    }
+}
+
+pub struct SwapIndexes<'a> {
+    pub proposer: MultiIndex<'a, String, Swap, u64>,
+}
+
+impl<'a> IndexList<Swap> for SwapIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Swap>> + '_> {
+        let v: Vec<&dyn Index<Swap>> = vec![&self.proposer];
+        Box::new(v.into_iter())
+    }
+}
+
+pub fn swaps<'a>() -> IndexedMap<'a, u64, Swap, SwapIndexes<'a>> {
+    let indexes = SwapIndexes {
+        proposer: MultiIndex::new(|_pk, s| s.proposer.clone(), "swaps", "swaps__proposer"),
+    };
+    IndexedMap::new("swaps", indexes)
+}
+
+pub const SWAP_SEQ: Item<u64> = Item::new("swap_seq");
+
+/// English auction on an escrowed token. Bids only ever go up, and the best bid is held by the exchange.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct Auction {
+    pub token_id: String,
+    pub seller: String,
+    pub reserve_price: Uint128, // lowest acceptable first bid
+    pub min_increment: Uint128, // each bid must beat the last by at least this much
+    pub end_time: u64, // seconds, pushed back by late bids
+    pub highest_bid: Option<Bid>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct Bid {
+    pub bidder: String,
+    pub amount: Uint128,
+}
+
+pub const AUCTIONS: Map<&str, Auction> = Map::new("auctions");
+
+/// A completed sale, from a buy, an accepted offer or a settled auction.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct Sale {
+    pub id: u64,
+    pub token_id: String,
+    pub seller: String,
+    pub buyer: String,
+    pub price: Uint128,
+    pub denom: Denom,
+    pub time: u64, // block time in seconds
+}
+
+pub struct SaleIndexes<'a> {
+    pub token: MultiIndex<'a, String, Sale, u64>,
+}
+
+impl<'a> IndexList<Sale> for SaleIndexes<'a> {
+    fn get_indexes(&'_ self) -> Box<dyn Iterator<Item = &'_ dyn Index<Sale>> + '_> {
+        let v: Vec<&dyn Index<Sale>> = vec![&self.token];
+        Box::new(v.into_iter())
+    }
+}
+
+/// Trade history, keyed by a sequential id.
+pub fn sales<'a>() -> IndexedMap<'a, u64, Sale, SaleIndexes<'a>> {
+    let indexes = SaleIndexes {
+        token: MultiIndex::new(|_pk, s| s.token_id.clone(), "sales", "sales__token"),
+    };
+    IndexedMap::new("sales", indexes)
+}
+
+pub const SALE_SEQ: Item<u64> = Item::new("sale_seq");
+
+/// Running totals for sales in one denom, volumes in different denoms don't add up.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, JsonSchema, Eq)]
+pub struct SaleStats {
+    pub denom: Denom,
+    pub sales: u64,
+    pub volume: Uint128,
+    pub last_price: Uint128,
+    pub all_time_high: Uint128,
+}
+
+/// Sale stats keyed by the denom as it appears in events.
+pub const STATS: Map<&str, SaleStats> = Map::new("stats");
+
+/// Adds a sale to the history and the stats of its denom.
+pub fn record_sale(storage: &mut dyn Storage, token_id: String, seller: String, buyer: String, price: Uint128, denom: Denom, time: u64) -> StdResult<Sale> {
+    let id = SALE_SEQ.may_load(storage)?.unwrap_or_default() + 1;
+    SALE_SEQ.save(storage, &id)?;
+    let sale = Sale { id, token_id, seller, buyer, price, denom, time };
+    sales().save(storage, id, &sale)?;
+
+    let key = sale.denom.to_string();
+    let mut stats = STATS.may_load(storage, &key)?.unwrap_or(SaleStats {
+        denom: sale.denom.clone(),
+        sales: 0,
+        volume: Uint128::zero(),
+        last_price: Uint128::zero(),
+        all_time_high: Uint128::zero(),
+    });
+    stats.sales += 1;
+    stats.volume += price;
+    stats.last_price = price;
+    stats.all_time_high = stats.all_time_high.max(price);
+    STATS.save(storage, &key, &stats)?;
+
+    Ok(sale)
+}
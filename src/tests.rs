@@ -1,13 +1,28 @@
 use std::str::FromStr;
 
-use cosmwasm_std::{Addr, Uint128, Decimal, Empty, coins};
-use cw_multi_test::{App, ContractWrapper, Executor};
+use cosmwasm_std::{to_binary, Addr, Uint128, Decimal, Empty, coins};
+use cw_multi_test::{App, AppResponse, BankSudo, ContractWrapper, Executor, SudoMsg};
 use nft_multi_test::{self, cw721_contract};
 
-use crate::{contract::*, msg::{InstantiateMsg, Creator, ExecuteMsg}, ContractError};
+use serde::{Deserialize, Serialize};
+
+use crate::{contract::*, msg::{InstantiateMsg, Creator, ExecuteMsg, QueryMsg, GetListedResponse, ListedCursor, PriceOrder, OwnerOf, ReceiveNftMsg, OffersResponse, CollectionOffersResponse, DutchAuction, Decay, DutchPricesResponse, Denom, Cw20HookMsg, FeeRecipient, SimulateBuyResponse, Payment, GetSalesResponse, GetStatsResponse, FloorResponse, ListingResponse, RolesResponse, GetMetadataResponse, ListItem, SwapItem, SwapsResponse}, ContractError};
 
 type Extension = Option<Empty>;
 
+#[derive(Serialize, Deserialize)]
+struct OwnerOfQuery {
+    owner_of: OwnerOf
+}
+
+fn owner_of(app: &App, nft: &Addr, id: &str) -> String {
+    let res: GetOwnerResponse = app
+        .wrap()
+        .query_wasm_smart(nft, &OwnerOfQuery { owner_of: OwnerOf { token_id: id.to_string() } })
+        .unwrap();
+    res.owner
+}
+
 #[test]
 fn init() {
     let mut app = App::default();
@@ -35,9 +50,12 @@ fn init() {
             supply: 100,
             creators: vec![Creator {
                 address: "creator".to_string(),
-                share: 100,
+                share: 10_000,
             }],
             basis_points: 100,
+            fee_basis_points: None,
+            fee_recipients: None,
+            max_royalty_basis_points: None,
         }, 
         &vec![], 
         "Instantiate Exchange Contract", 
@@ -72,9 +90,12 @@ fn list() {
             supply: 100,
             creators: vec![Creator {
                 address: "creator".to_string(),
-                share: 100,
+                share: 10_000,
             }],
             basis_points: 100,
+            fee_basis_points: None,
+            fee_recipients: None,
+            max_royalty_basis_points: None,
         }, 
         &vec![], 
         "Instantiate Exchange Contract", 
@@ -110,7 +131,9 @@ fn list() {
         &ExecuteMsg::List {
             id: 0.to_string(),
             price: Uint128::new(1000000),
-            expires: 0
+            expires: 0,
+            denom: None,
+            reserved_for: None
         },
         &vec![],
     ).expect("could not list");
@@ -148,9 +171,12 @@ fn buy() {
             supply: 100,
             creators: vec![Creator {
                 address: "creator".to_string(),
-                share: 100,
+                share: 10_000,
             }],
             basis_points: 100,
+            fee_basis_points: None,
+            fee_recipients: None,
+            max_royalty_basis_points: None,
         }, 
         &vec![], 
         "Instantiate Exchange Contract", 
@@ -186,7 +212,9 @@ fn buy() {
         &ExecuteMsg::List {
             id: 0.to_string(),
             price: Uint128::new(1000000),
-            expires: 0
+            expires: 0,
+            denom: None,
+            reserved_for: None
         },
         &vec![],
     ).expect("could not list");
@@ -228,9 +256,12 @@ fn dup_listing() {
             supply: 100,
             creators: vec![Creator {
                 address: "creator".to_string(),
-                share: 100,
+                share: 10_000,
             }],
             basis_points: 100,
+            fee_basis_points: None,
+            fee_recipients: None,
+            max_royalty_basis_points: None,
         }, 
         &vec![], 
         "Instantiate Exchange Contract", 
@@ -267,7 +298,9 @@ fn dup_listing() {
         &ExecuteMsg::List {
             id: 0.to_string(),
             price: Uint128::new(1000000),
-            expires: 0
+            expires: 0,
+            denom: None,
+            reserved_for: None
         },
         &vec![],
     ).expect("could not list");
@@ -278,7 +311,9 @@ fn dup_listing() {
         &ExecuteMsg::List {
             id: 0.to_string(),
             price: Uint128::new(1000000),
-            expires: 0
+            expires: 0,
+            denom: None,
+            reserved_for: None
         },
         &vec![],
     ).unwrap_err().downcast().unwrap();
@@ -313,9 +348,12 @@ fn delist_deauth() {
             supply: 100,
             creators: vec![Creator {
                 address: "creator".to_string(),
-                share: 100,
+                share: 10_000,
             }],
             basis_points: 100,
+            fee_basis_points: None,
+            fee_recipients: None,
+            max_royalty_basis_points: None,
         }, 
         &vec![], 
         "Instantiate Exchange Contract", 
@@ -351,7 +389,9 @@ fn delist_deauth() {
         &ExecuteMsg::List {
             id: 0.to_string(),
             price: Uint128::new(1000000),
-            expires: 0
+            expires: 0,
+            denom: None,
+            reserved_for: None
         },
         &vec![],
     ).expect("could not list");
@@ -395,9 +435,12 @@ fn delist() {
             supply: 100,
             creators: vec![Creator {
                 address: "creator".to_string(),
-                share: 100,
+                share: 10_000,
             }],
             basis_points: 100,
+            fee_basis_points: None,
+            fee_recipients: None,
+            max_royalty_basis_points: None,
         }, 
         &vec![], 
         "Instantiate Exchange Contract", 
@@ -433,7 +476,9 @@ fn delist() {
         &ExecuteMsg::List {
             id: 0.to_string(),
             price: Uint128::new(1000000),
-            expires: 0
+            expires: 0,
+            denom: None,
+            reserved_for: None
         },
         &vec![],
     ).expect("could not list");
@@ -468,4 +513,1870 @@ fn cw_math_royalties() {
     assert_eq!(royalty, price * royalties);
     assert_eq!(royalty, Uint128::new(69000000000000000));
     assert_eq!(fee, Uint128::new(30000000000000000));
-}
\ No newline at end of file
+}
+
+fn setup(balances: Vec<(&str, u128)>) -> (App, Addr, Addr) {
+    let mut app = App::new(|router, _, storage| {
+        for (addr, amount) in balances {
+            router
+                .bank
+                .init_balance(storage, &Addr::unchecked(addr), coins(amount, "inj"))
+                .unwrap()
+        }
+    });
+
+    let code = ContractWrapper::new(execute, instantiate, query).with_migrate(migrate);
+    let code_id = app.store_code(Box::new(code));
+    let nft_code = cw721_contract();
+    let nft_code_id = app.store_code(nft_code);
+    let nft = nft_multi_test::instantiate(&mut app, nft_code_id, &nft_multi_test::InstantiateMsg {
+        name: "Test Collection".to_string(),
+        symbol: "TEST".to_string(),
+        minter: "owner".to_string(),
+    }).expect("Could not instantiate nft contract");
+
+    let exchange = app.instantiate_contract(
+        code_id, 
+        Addr::unchecked("owner"), 
+        &InstantiateMsg {
+            collection: "collection".to_string(),
+            contract: (&nft).to_string(),
+            description: "Test collection on Nebula".to_string(),
+            symbol: "TEST".to_string(),
+            logo_uri: "https://example.com/logo.png".to_string(),
+            banner_uri: "https://example.com/banner.png".to_string(),
+            supply: 100,
+            creators: vec![Creator {
+                address: "creator".to_string(),
+                share: 10_000,
+            }],
+            basis_points: 100,
+            fee_basis_points: None,
+            fee_recipients: None,
+            max_royalty_basis_points: None,
+        }, 
+        &vec![], 
+        "Instantiate Exchange Contract", 
+        Some("owner".to_string())
+    ).expect("contract failed to instantiate");
+
+    (app, Addr::unchecked(nft), exchange)
+}
+
+fn mint_and_approve(app: &mut App, nft: &Addr, exchange: &Addr, id: &str, owner: &str) {
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::Mint(nft_multi_test::MintMsg::<Extension> {
+            token_id: id.to_string(),
+            owner: owner.to_string(),
+            token_uri: Some("token_uri".to_string()),
+            extension: None
+        }),
+        &vec![]
+    ).expect("Minting is borked");
+
+    app.execute_contract(
+        Addr::unchecked(owner),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::Approve { 
+            token_id: id.to_string(),
+            spender: exchange.to_string(),
+            expires: None
+        },
+        &vec![]
+    ).expect("approval is borked");
+}
+
+#[test]
+fn listed_sorted_by_price() {
+    let (mut app, nft, exchange) = setup(vec![]);
+
+    for (id, price) in [("0", 3000000u128), ("1", 1000000), ("2", 2000000)] {
+        mint_and_approve(&mut app, &nft, &exchange, id, "owner");
+        app.execute_contract(
+            Addr::unchecked("owner"),
+            exchange.clone(),
+            &ExecuteMsg::List {
+                id: id.to_string(),
+                price: Uint128::new(price),
+                expires: 0,
+                denom: None,
+                reserved_for: None
+            },
+            &vec![],
+        ).expect("could not list");
+    }
+
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::DeList {
+            id: "2".to_string(),
+        },
+        &vec![]
+    ).expect("could not delist");
+
+    let listed: GetListedResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::GetListed {
+            start_after: None,
+            limit: None,
+            order: None,
+            min_price: None,
+            max_price: None,
+            owner: None,
+            include_expired: None,
+        })
+        .unwrap();
+    let ids: Vec<&str> = listed.listed.iter().map(|t| t.id.as_str()).collect();
+    assert_eq!(ids, vec!["1", "0"]);
+    assert_eq!(listed.number, 2);
+}
+
+fn list_token(app: &mut App, exchange: &Addr, id: &str, owner: &str, price: u128) {
+    list_token_until(app, exchange, id, owner, price, 0);
+}
+
+fn list_token_until(app: &mut App, exchange: &Addr, id: &str, owner: &str, price: u128, expires: i128) {
+    app.execute_contract(
+        Addr::unchecked(owner),
+        exchange.clone(),
+        &ExecuteMsg::List {
+            id: id.to_string(),
+            price: Uint128::new(price),
+            expires,
+            denom: None,
+            reserved_for: None
+        },
+        &vec![],
+    ).expect("could not list");
+}
+
+/// Attributes of the exchange's own wasm event, without `_contract_addr`
+fn exchange_attributes(res: &AppResponse, exchange: &Addr) -> Vec<(String, String)> {
+    let event = res.events
+        .iter()
+        .find(|e| e.ty == "wasm" && e.attributes.iter().any(|a| a.key == "_contract_addr" && a.value == exchange.as_str()))
+        .expect("no exchange event");
+    event.attributes
+        .iter()
+        .filter(|a| a.key != "_contract_addr")
+        .map(|a| (a.key.clone(), a.value.clone()))
+        .collect()
+}
+
+fn listed_ids(app: &App, exchange: &Addr, include_expired: bool) -> Vec<String> {
+    let res: GetListedResponse = app
+        .wrap()
+        .query_wasm_smart(exchange, &QueryMsg::GetListed {
+            start_after: None,
+            limit: None,
+            order: None,
+            min_price: None,
+            max_price: None,
+            owner: None,
+            include_expired: Some(include_expired),
+        })
+        .unwrap();
+    res.listed.into_iter().map(|t| t.id).collect()
+}
+
+#[test]
+fn listed_paginated_and_filtered() {
+    let (mut app, nft, exchange) = setup(vec![]);
+
+    for (id, owner, price) in [("0", "owner", 1000u128), ("1", "alice", 2000), ("2", "owner", 2000), ("3", "owner", 3000), ("4", "alice", 4000)] {
+        mint_and_approve(&mut app, &nft, &exchange, id, owner);
+        list_token(&mut app, &exchange, id, owner, price);
+    }
+
+    let page = |app: &App, start_after: Option<ListedCursor>, order: Option<PriceOrder>, min_price: Option<u128>, max_price: Option<u128>, owner: Option<&str>| -> Vec<String> {
+        let res: GetListedResponse = app
+            .wrap()
+            .query_wasm_smart(&exchange, &QueryMsg::GetListed {
+                start_after,
+                limit: Some(2),
+                order,
+                min_price: min_price.map(Uint128::new),
+                max_price: max_price.map(Uint128::new),
+                owner: owner.map(String::from),
+                include_expired: None,
+            })
+            .unwrap();
+        assert_eq!(res.number, 5);
+        res.listed.into_iter().map(|t| t.id).collect()
+    };
+
+    assert_eq!(page(&app, None, None, None, None, None), vec!["0", "1"]);
+    let cursor = ListedCursor { price: Uint128::new(2000), id: "1".to_string() };
+    assert_eq!(page(&app, Some(cursor), None, None, None, None), vec!["2", "3"]);
+    assert_eq!(page(&app, None, Some(PriceOrder::Descending), None, None, None), vec!["4", "3"]);
+    let cursor = ListedCursor { price: Uint128::new(3000), id: "3".to_string() };
+    assert_eq!(page(&app, Some(cursor), Some(PriceOrder::Descending), None, None, None), vec!["2", "1"]);
+    assert_eq!(page(&app, None, None, Some(2000), Some(3000), None), vec!["1", "2"]);
+    assert_eq!(page(&app, None, None, None, None, Some("alice")), vec!["1", "4"]);
+    assert_eq!(page(&app, None, Some(PriceOrder::Descending), None, Some(3000), Some("owner")), vec!["3", "2"]);
+    let cursor = ListedCursor { price: Uint128::new(2000), id: "2".to_string() };
+    assert_eq!(page(&app, Some(cursor), None, None, None, Some("owner")), vec!["3"]);
+
+    // a burned token still lists, just without a uri
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::Burn { token_id: "0".to_string() },
+        &vec![],
+    ).expect("could not burn");
+    let res: GetListedResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::GetListed {
+            start_after: None,
+            limit: Some(2),
+            order: None,
+            min_price: None,
+            max_price: None,
+            owner: None,
+            include_expired: None,
+        })
+        .unwrap();
+    assert_eq!((res.listed[0].id.as_str(), res.listed[0].uri.as_str()), ("0", ""));
+    assert_eq!(res.listed[1].uri, "token_uri");
+}
+
+#[test]
+fn expired_listing() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2010000)]);
+    let now = app.block_info().time.seconds() as i128;
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    mint_and_approve(&mut app, &nft, &exchange, "1", "owner");
+    mint_and_approve(&mut app, &nft, &exchange, "2", "owner");
+    list_token_until(&mut app, &exchange, "0", "owner", 1000000, now + 100);
+    list_token_until(&mut app, &exchange, "1", "owner", 1000000, now + 200);
+    list_token(&mut app, &exchange, "2", "owner", 1000000);
+
+    app.update_block(|block| block.time = block.time.plus_seconds(150));
+
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy {
+            id: "0".to_string(),
+        },
+        &coins(1010000, "inj"),
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::ListingExpired {});
+
+    assert_eq!(listed_ids(&app, &exchange, false), vec!["1", "2"]);
+    assert_eq!(listed_ids(&app, &exchange, true), vec!["0", "1", "2"]);
+
+    app.execute_contract(
+        Addr::unchecked("anyone"),
+        exchange.clone(),
+        &ExecuteMsg::PurgeExpired { limit: None },
+        &vec![],
+    ).expect("could not purge");
+    assert_eq!(listed_ids(&app, &exchange, true), vec!["1", "2"]);
+
+    // an expired listing can be relisted by its owner
+    list_token_until(&mut app, &exchange, "0", "owner", 1000000, now + 1000);
+    assert_eq!(listed_ids(&app, &exchange, false), vec!["0", "1", "2"]);
+}
+
+fn list_escrow(app: &mut App, nft: &Addr, exchange: &Addr, id: &str, owner: &str, price: u128, expires: i128) {
+    app.execute_contract(
+        Addr::unchecked(owner),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::SendNft {
+            contract: exchange.to_string(),
+            token_id: id.to_string(),
+            msg: to_binary(&ReceiveNftMsg::List { price: Uint128::new(price), expires, denom: None }).unwrap(),
+        },
+        &vec![],
+    ).expect("could not list in escrow");
+}
+
+#[test]
+fn escrow_listing() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2010000)]);
+    let now = app.block_info().time.seconds() as i128;
+
+    for id in ["0", "1", "2"] {
+        mint_and_approve(&mut app, &nft, &exchange, id, "owner");
+        list_escrow(&mut app, &nft, &exchange, id, "owner", 1000000, now + 100);
+        assert_eq!(owner_of(&app, &nft, id), exchange.to_string());
+    }
+    assert_eq!(listed_ids(&app, &exchange, false), vec!["0", "1", "2"]);
+
+    // delisting hands the token back
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::DeList { id: "0".to_string() },
+        &vec![]
+    ).expect("could not delist");
+    assert_eq!(owner_of(&app, &nft, "0"), "owner");
+
+    app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "1".to_string() },
+        &coins(1010000, "inj"),
+    ).expect("could not buy");
+    assert_eq!(owner_of(&app, &nft, "1"), "buyer");
+
+    // expired escrow listings are returned by the sweeper
+    app.update_block(|block| block.time = block.time.plus_seconds(150));
+    app.execute_contract(
+        Addr::unchecked("anyone"),
+        exchange.clone(),
+        &ExecuteMsg::PurgeExpired { limit: None },
+        &vec![],
+    ).expect("could not purge");
+    assert_eq!(owner_of(&app, &nft, "2"), "owner");
+    assert!(listed_ids(&app, &exchange, true).is_empty());
+}
+
+#[test]
+fn escrow_wrong_collection() {
+    let (mut app, _, exchange) = setup(vec![]);
+
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("other_nft"),
+        exchange,
+        &ExecuteMsg::ReceiveNft(crate::msg::Cw721ReceiveMsg {
+            sender: "owner".to_string(),
+            token_id: "0".to_string(),
+            msg: to_binary(&ReceiveNftMsg::List { price: Uint128::new(1), expires: 0, denom: None }).unwrap(),
+        }),
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Unauthorized {});
+}
+
+#[test]
+fn offers() {
+    let (mut app, nft, exchange) = setup(vec![("alice", 5000000), ("bob", 5000000)]);
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+
+    for (bidder, amount) in [("alice", 1000000u128), ("bob", 2000000)] {
+        app.execute_contract(
+            Addr::unchecked(bidder),
+            exchange.clone(),
+            &ExecuteMsg::MakeOffer { id: "0".to_string(), expires: 0 },
+            &coins(amount, "inj"),
+        ).expect("could not make offer");
+    }
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(4000000));
+
+    let res: OffersResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::OffersByToken { id: "0".to_string(), start_after: None, limit: None })
+        .unwrap();
+    assert_eq!(res.offers.len(), 2);
+    let res: OffersResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::OffersByBidder { bidder: "bob".to_string(), start_after: None, limit: None })
+        .unwrap();
+    assert_eq!(res.offers[0].price, Uint128::new(2000000));
+
+    // only the owner can accept
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &ExecuteMsg::AcceptOffer { id: "0".to_string(), bidder: "bob".to_string() },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Unauthorized {});
+
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::AcceptOffer { id: "0".to_string(), bidder: "bob".to_string() },
+        &vec![],
+    ).expect("could not accept offer");
+    assert_eq!(owner_of(&app, &nft, "0"), "bob");
+
+    app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &ExecuteMsg::CancelOffer { id: "0".to_string() },
+        &vec![],
+    ).expect("could not cancel offer");
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(5000000));
+}
+
+#[test]
+fn collection_offers() {
+    let (mut app, nft, exchange) = setup(vec![("alice", 5000000)]);
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    mint_and_approve(&mut app, &nft, &exchange, "1", "bob");
+
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &ExecuteMsg::MakeCollectionOffer { price: Uint128::zero(), quantity: 3, expires: 0 },
+        &coins(1, "inj"),
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::InsufficientFunds {});
+
+    // overpayment is refunded straight away
+    app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &ExecuteMsg::MakeCollectionOffer { price: Uint128::new(1000000), quantity: 3, expires: 0 },
+        &coins(3500000, "inj"),
+    ).expect("could not make collection offer");
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(2000000));
+
+    for (id, seller) in [("0", "owner"), ("1", "bob")] {
+        app.execute_contract(
+            Addr::unchecked(seller),
+            exchange.clone(),
+            &ExecuteMsg::AcceptCollectionOffer { offer_id: 1, token_id: id.to_string() },
+            &vec![],
+        ).expect("could not accept collection offer");
+        assert_eq!(owner_of(&app, &nft, id), "alice");
+    }
+
+    let res: CollectionOffersResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::CollectionOffers { start_after: None, limit: None })
+        .unwrap();
+    assert_eq!(res.offers[0].quantity, 1);
+
+    app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &ExecuteMsg::CancelCollectionOffer { offer_id: 1 },
+        &vec![],
+    ).expect("could not cancel collection offer");
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(3000000));
+
+    let res: CollectionOffersResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::CollectionOffers { start_after: None, limit: None })
+        .unwrap();
+    assert!(res.offers.is_empty());
+}
+
+#[test]
+fn english_auction() {
+    let (mut app, nft, exchange) = setup(vec![("alice", 5000000), ("bob", 5000000)]);
+    let now = app.block_info().time.seconds();
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    let err: ContractError = app.execute_contract(
+        nft.clone(),
+        exchange.clone(),
+        &ExecuteMsg::ReceiveNft(crate::msg::Cw721ReceiveMsg {
+            sender: "owner".to_string(),
+            token_id: "0".to_string(),
+            msg: to_binary(&ReceiveNftMsg::Auction {
+                reserve_price: Uint128::new(1000000),
+                min_increment: Uint128::zero(),
+                end_time: now + 1000,
+            }).unwrap(),
+        }),
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::InvalidIncrement {});
+
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::SendNft {
+            contract: exchange.to_string(),
+            token_id: "0".to_string(),
+            msg: to_binary(&ReceiveNftMsg::Auction {
+                reserve_price: Uint128::new(1000000),
+                min_increment: Uint128::new(100000),
+                end_time: now + 1000,
+            }).unwrap(),
+        },
+        &vec![],
+    ).expect("could not start auction");
+
+    let bid = |app: &mut App, bidder: &str, amount: u128| app.execute_contract(
+        Addr::unchecked(bidder),
+        exchange.clone(),
+        &ExecuteMsg::PlaceBid { id: "0".to_string() },
+        &coins(amount, "inj"),
+    );
+
+    let err: ContractError = bid(&mut app, "alice", 900000).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::BidTooLow { min: Uint128::new(1000000) });
+    bid(&mut app, "alice", 1000000).expect("could not bid");
+    let err: ContractError = bid(&mut app, "bob", 1050000).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::BidTooLow { min: Uint128::new(1100000) });
+    bid(&mut app, "bob", 1100000).expect("could not bid");
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(5000000));
+
+    // a bid in the last minutes extends the auction
+    app.update_block(|block| block.time = block.time.plus_seconds(900));
+    bid(&mut app, "alice", 1200000).expect("could not bid");
+    let auction: crate::state::Auction = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::Auction { id: "0".to_string() })
+        .unwrap();
+    assert_eq!(auction.end_time, now + 900 + 300);
+
+    app.update_block(|block| block.time = block.time.plus_seconds(200));
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("anyone"),
+        exchange.clone(),
+        &ExecuteMsg::SettleAuction { id: "0".to_string() },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::AuctionNotEnded {});
+
+    app.update_block(|block| block.time = block.time.plus_seconds(100));
+    app.execute_contract(
+        Addr::unchecked("anyone"),
+        exchange.clone(),
+        &ExecuteMsg::SettleAuction { id: "0".to_string() },
+        &vec![],
+    ).expect("could not settle");
+    assert_eq!(owner_of(&app, &nft, "0"), "alice");
+    assert_eq!(app.wrap().query_balance("bob", "inj").unwrap().amount, Uint128::new(5000000));
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(3800000));
+}
+
+#[test]
+fn dutch_auction() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 5000000)]);
+    let now = app.block_info().time.seconds();
+
+    for (id, decay) in [("0", Decay::Linear), ("1", Decay::Exponential)] {
+        mint_and_approve(&mut app, &nft, &exchange, id, "owner");
+        app.execute_contract(
+            Addr::unchecked("owner"),
+            exchange.clone(),
+            &ExecuteMsg::ListDutch {
+                id: id.to_string(),
+                price: Uint128::new(2000000),
+                dutch: DutchAuction {
+                    end_price: Uint128::new(1000000),
+                    start_time: now,
+                    end_time: now + 1000,
+                    decay,
+                },
+                expires: 0,
+                denom: None
+            },
+            &vec![],
+        ).expect("could not list");
+    }
+
+    app.update_block(|block| block.time = block.time.plus_seconds(500));
+
+    let res: DutchPricesResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::DutchPrices { start_after: None, limit: None })
+        .unwrap();
+    assert_eq!(res.prices[0].price, Uint128::new(1500000));
+    // 2000000 * sqrt(0.5)
+    assert!((1414213..=1414214).contains(&res.prices[1].price.u128()));
+
+    // overpayment comes back: only the 1500000 current price is due
+    app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(2020000, "inj"),
+    ).expect("could not buy");
+    assert_eq!(owner_of(&app, &nft, "0"), "buyer");
+    assert_eq!(app.wrap().query_balance("buyer", "inj").unwrap().amount, Uint128::new(5000000 - 1500000));
+
+    // the price bottoms out at the end price
+    app.update_block(|block| block.time = block.time.plus_seconds(1000));
+    let res: DutchPricesResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::DutchPrices { start_after: None, limit: None })
+        .unwrap();
+    assert_eq!(res.prices[0].price, Uint128::new(1000000));
+}
+
+/// Just enough of a cw20 to hold balances, transfer and send
+mod mock_cw20 {
+    use cosmwasm_schema::cw_serde;
+    use cosmwasm_std::{to_binary, Binary, Deps, DepsMut, Env, MessageInfo, Response, StdResult, Storage, Uint128, WasmMsg};
+    use cw_storage_plus::Map;
+
+    use crate::msg::Cw20ReceiveMsg;
+
+    const BALANCES: Map<&str, Uint128> = Map::new("balances");
+
+    #[cw_serde]
+    pub struct InstantiateMsg {
+        pub balances: Vec<(String, Uint128)>,
+    }
+
+    #[cw_serde]
+    pub enum ExecuteMsg {
+        Transfer { recipient: String, amount: Uint128 },
+        Send { contract: String, amount: Uint128, msg: Binary },
+    }
+
+    #[cw_serde]
+    pub enum QueryMsg {
+        Balance { address: String },
+    }
+
+    fn move_funds(storage: &mut dyn Storage, from: &str, to: &str, amount: Uint128) -> StdResult<()> {
+        BALANCES.update(storage, from, |b| -> StdResult<_> { Ok(b.unwrap_or_default().checked_sub(amount)?) })?;
+        BALANCES.update(storage, to, |b| -> StdResult<_> { Ok(b.unwrap_or_default() + amount) })?;
+        Ok(())
+    }
+
+    pub fn instantiate(deps: DepsMut, _env: Env, _info: MessageInfo, msg: InstantiateMsg) -> StdResult<Response> {
+        for (address, amount) in msg.balances {
+            BALANCES.save(deps.storage, &address, &amount)?;
+        }
+        Ok(Response::new())
+    }
+
+    pub fn execute(deps: DepsMut, _env: Env, info: MessageInfo, msg: ExecuteMsg) -> StdResult<Response> {
+        match msg {
+            ExecuteMsg::Transfer { recipient, amount } => {
+                move_funds(deps.storage, info.sender.as_str(), &recipient, amount)?;
+                Ok(Response::new())
+            }
+            ExecuteMsg::Send { contract, amount, msg } => {
+                move_funds(deps.storage, info.sender.as_str(), &contract, amount)?;
+                Ok(Response::new().add_message(WasmMsg::Execute {
+                    contract_addr: contract,
+                    msg: to_binary(&crate::msg::ExecuteMsg::Receive(Cw20ReceiveMsg {
+                        sender: info.sender.to_string(),
+                        amount,
+                        msg,
+                    }))?,
+                    funds: vec![],
+                }))
+            }
+        }
+    }
+
+    pub fn query(deps: Deps, _env: Env, msg: QueryMsg) -> StdResult<Binary> {
+        match msg {
+            QueryMsg::Balance { address } => to_binary(&BALANCES.may_load(deps.storage, &address)?.unwrap_or_default()),
+        }
+    }
+}
+
+#[test]
+fn buy_with_cw20() {
+    let (mut app, nft, exchange) = setup(vec![]);
+
+    let cw20_code_id = app.store_code(Box::new(ContractWrapper::new(mock_cw20::execute, mock_cw20::instantiate, mock_cw20::query)));
+    let cw20 = app.instantiate_contract(
+        cw20_code_id,
+        Addr::unchecked("owner"),
+        &mock_cw20::InstantiateMsg { balances: vec![("buyer".to_string(), Uint128::new(5000000))] },
+        &vec![],
+        "Mock cw20",
+        None
+    ).expect("could not instantiate cw20");
+    let cw20_balance = |app: &App, address: &str| -> Uint128 {
+        app.wrap().query_wasm_smart(&cw20, &mock_cw20::QueryMsg::Balance { address: address.to_string() }).unwrap()
+    };
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::List {
+            id: "0".to_string(),
+            price: Uint128::new(1000000),
+            expires: 0,
+            denom: Some(Denom::Cw20(cw20.to_string())),
+            reserved_for: None
+        },
+        &vec![],
+    ).expect("could not list");
+
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::InvalidDenom {});
+
+    // only the listed price is due, the rest is refunded
+    app.execute_contract(
+        Addr::unchecked("buyer"),
+        cw20.clone(),
+        &mock_cw20::ExecuteMsg::Send {
+            contract: exchange.to_string(),
+            amount: Uint128::new(1100000),
+            msg: to_binary(&Cw20HookMsg::Buy { id: "0".to_string() }).unwrap(),
+        },
+        &vec![],
+    ).expect("could not buy with cw20");
+
+    assert_eq!(owner_of(&app, &nft, "0"), "buyer");
+    assert_eq!(cw20_balance(&app, "buyer"), Uint128::new(5000000 - 1000000));
+    assert_eq!(cw20_balance(&app, "creator"), Uint128::new(10000));
+}
+
+#[test]
+fn accepted_denoms() {
+    let (mut app, nft, exchange) = setup(vec![]);
+    let usdt = "peggy0xdAC17F958D2ee523a2206206994597C13D831ec7";
+    app.sudo(SudoMsg::Bank(BankSudo::Mint { to_address: "buyer".to_string(), amount: coins(2000000, usdt) })).unwrap();
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    let list = |app: &mut App, denom: &str| app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::List {
+            id: "0".to_string(),
+            price: Uint128::new(1000000),
+            expires: 0,
+            denom: Some(Denom::Native(denom.to_string())),
+            reserved_for: None
+        },
+        &vec![],
+    );
+
+    let err: ContractError = list(&mut app, usdt).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::DenomNotAccepted { denom: usdt.to_string() });
+
+    let update = ExecuteMsg::UpdateConfig {
+        accepted_denoms: Some(vec!["inj".to_string(), usdt.to_string()]),
+        fee_basis_points: None,
+        fee_recipients: None,
+        max_royalty_basis_points: None,
+    };
+    let err: ContractError = app.execute_contract(Addr::unchecked("bad_actor"), exchange.clone(), &update, &vec![])
+        .unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Unauthorized {});
+    app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &update, &vec![]).expect("could not update config");
+
+    list(&mut app, usdt).expect("could not list");
+    let res: GetListedResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::GetListed {
+            start_after: None,
+            limit: None,
+            order: None,
+            min_price: None,
+            max_price: None,
+            owner: None,
+            include_expired: None,
+        })
+        .unwrap();
+    assert_eq!(res.listed[0].denom, Denom::Native(usdt.to_string()));
+
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::InsufficientFunds {});
+
+    app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1010000, usdt),
+    ).expect("could not buy");
+    assert_eq!(owner_of(&app, &nft, "0"), "buyer");
+    assert_eq!(app.wrap().query_balance("creator", usdt).unwrap().amount, Uint128::new(10000));
+}
+
+#[test]
+fn platform_fee_config() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2010000)]);
+
+    let update = |fee_basis_points: u16, shares: [u16; 2]| ExecuteMsg::UpdateConfig {
+        accepted_denoms: None,
+        fee_basis_points: Some(fee_basis_points),
+        fee_recipients: Some(vec![
+            FeeRecipient { address: "treasury".to_string(), share: shares[0] },
+            FeeRecipient { address: "dev".to_string(), share: shares[1] },
+        ]),
+        max_royalty_basis_points: None,
+    };
+
+    let err: ContractError = app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &update(2000, [5000, 5000]), &vec![])
+        .unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::FeeTooHigh { max: 1000 });
+    let err: ContractError = app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &update(500, [5000, 4000]), &vec![])
+        .unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::InvalidFeeRecipients {});
+    app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &update(500, [5000, 5000]), &vec![])
+        .expect("could not update config");
+
+    let config: crate::state::Config = app.wrap().query_wasm_smart(&exchange, &QueryMsg::Config {}).unwrap();
+    assert_eq!(config.fee_basis_points, 500);
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    list_token(&mut app, &exchange, "0", "owner", 1000000);
+    app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1010000, "inj"),
+    ).expect("could not buy");
+
+    // 5% of the price split evenly, the overpayment goes back to the buyer
+    assert_eq!(app.wrap().query_balance("treasury", "inj").unwrap().amount, Uint128::new(25000));
+    assert_eq!(app.wrap().query_balance("dev", "inj").unwrap().amount, Uint128::new(25000));
+    assert_eq!(app.wrap().query_balance("buyer", "inj").unwrap().amount, Uint128::new(1010000));
+}
+
+#[test]
+fn simulate_buy() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2000000)]);
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    list_token(&mut app, &exchange, "0", "owner", 1000001);
+
+    let res: SimulateBuyResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::SimulateBuy { id: "0".to_string() })
+        .unwrap();
+    assert_eq!(res.price, Uint128::new(1000001));
+    assert_eq!(res.royalties, vec![Payment { address: "creator".to_string(), amount: Uint128::new(10000) }]);
+    assert_eq!(res.fees[0].amount, Uint128::new(30000));
+    assert_eq!(res.seller, Payment { address: "owner".to_string(), amount: Uint128::new(960001) });
+
+    // the breakdown is exactly what gets paid out
+    app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1000001, "inj"),
+    ).expect("could not buy");
+    assert_eq!(app.wrap().query_balance("creator", "inj").unwrap().amount, Uint128::new(10000));
+    assert_eq!(app.wrap().query_balance(&res.fees[0].address, "inj").unwrap().amount, Uint128::new(30000));
+    assert_eq!(app.wrap().query_balance("owner", "inj").unwrap().amount, Uint128::new(960001));
+    assert_eq!(app.wrap().query_balance("buyer", "inj").unwrap().amount, Uint128::new(999999));
+
+    let err = app.wrap().query_wasm_smart::<SimulateBuyResponse>(&exchange, &QueryMsg::SimulateBuy { id: "0".to_string() });
+    assert!(err.is_err());
+}
+
+#[test]
+fn royalty_validation() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2000000)]);
+
+    let update = |creators: Vec<(&str, u16)>, basis_points: u16| ExecuteMsg::UpdateMetadata {
+        creators: Some(creators
+            .into_iter()
+            .map(|(address, share)| Creator { address: address.to_string(), share })
+            .collect()),
+        collection: None,
+        website: None,
+        contact: None,
+        twitter: None,
+        telegram: None,
+        discord: None,
+        description: None,
+        logo_uri: None,
+        banner_uri: None,
+        basis_points: Some(basis_points),
+    };
+    let update_err = |app: &mut App, msg: &ExecuteMsg| -> ContractError {
+        app.execute_contract(Addr::unchecked("owner"), exchange.clone(), msg, &vec![])
+            .unwrap_err().downcast().unwrap()
+    };
+
+    let err = update_err(&mut app, &update(vec![("creator", 6000), ("artist", 6000)], 500));
+    assert_eq!(err, ContractError::InvalidCreatorShares {});
+    let err = update_err(&mut app, &update(vec![], 500));
+    assert_eq!(err, ContractError::InvalidCreatorShares {});
+    let err = update_err(&mut app, &update(vec![("creator", 10_000)], 1500));
+    assert_eq!(err, ContractError::RoyaltyTooHigh { max: 1000 });
+    let err = update_err(&mut app, &update(vec![("creator", 5000), ("NotAnAddress", 5000)], 500));
+    assert!(matches!(err, ContractError::Std(_)));
+
+    // the cap is part of the exchange config
+    app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &ExecuteMsg::UpdateConfig {
+        accepted_denoms: None,
+        fee_basis_points: None,
+        fee_recipients: None,
+        max_royalty_basis_points: Some(2000),
+    }, &vec![]).expect("could not raise the royalty cap");
+    app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &update(vec![("creator", 7500), ("artist", 2500)], 1500), &vec![])
+        .expect("could not update royalties");
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    list_token(&mut app, &exchange, "0", "owner", 1000000);
+    app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1000000, "inj"),
+    ).expect("could not buy");
+    assert_eq!(app.wrap().query_balance("creator", "inj").unwrap().amount, Uint128::new(112500));
+    assert_eq!(app.wrap().query_balance("artist", "inj").unwrap().amount, Uint128::new(37500));
+}
+
+#[test]
+fn event_attributes() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2000000)]);
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    let pairs = |v: &[(&str, &str)]| v.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect::<Vec<_>>();
+
+    let res = app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::List { id: "0".to_string(), price: Uint128::new(1000000), expires: 0, denom: None, reserved_for: None },
+        &vec![],
+    ).expect("could not list");
+    assert_eq!(exchange_attributes(&res, &exchange), pairs(&[
+        ("action", "list"),
+        ("token_id", "0"),
+        ("seller", "owner"),
+        ("price", "1000000"),
+        ("denom", "inj"),
+        ("expires", "0"),
+        ("escrowed", "false"),
+        ("kind", "fixed"),
+    ]));
+
+    let res = app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1000000, "inj"),
+    ).expect("could not buy");
+    assert_eq!(exchange_attributes(&res, &exchange), pairs(&[
+        ("action", "buy"),
+        ("token_id", "0"),
+        ("buyer", "buyer"),
+        ("seller", "owner"),
+        ("price", "1000000"),
+        ("denom", "inj"),
+        ("royalty", "10000"),
+        ("fee", "30000"),
+        ("seller_amount", "960000"),
+    ]));
+
+    mint_and_approve(&mut app, &nft, &exchange, "1", "owner");
+    list_token(&mut app, &exchange, "1", "owner", 1000000);
+    let res = app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &ExecuteMsg::DeList { id: "1".to_string() }, &vec![])
+        .expect("could not delist");
+    assert_eq!(exchange_attributes(&res, &exchange), pairs(&[("action", "delist"), ("token_id", "1"), ("seller", "owner")]));
+
+    let res = app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &ExecuteMsg::Flag { enabled: true, reason: Some("spam".to_string()) }, &vec![])
+        .expect("could not flag");
+    assert_eq!(exchange_attributes(&res, &exchange), pairs(&[("action", "flag"), ("flagged", "true"), ("reason", "spam")]));
+}
+
+#[test]
+fn sales_history() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 5000000), ("alice", 5000000)]);
+
+    for (id, price) in [("0", 1000000u128), ("1", 2000000)] {
+        mint_and_approve(&mut app, &nft, &exchange, id, "owner");
+        list_token(&mut app, &exchange, id, "owner", price);
+        app.execute_contract(
+            Addr::unchecked("buyer"),
+            exchange.clone(),
+            &ExecuteMsg::Buy { id: id.to_string() },
+            &coins(price, "inj"),
+        ).expect("could not buy");
+    }
+
+    // sales through offers are recorded too
+    mint_and_approve(&mut app, &nft, &exchange, "2", "owner");
+    app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &ExecuteMsg::MakeOffer { id: "2".to_string(), expires: 0 },
+        &coins(1500000, "inj"),
+    ).expect("could not make offer");
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::AcceptOffer { id: "2".to_string(), bidder: "alice".to_string() },
+        &vec![],
+    ).expect("could not accept offer");
+
+    let get_sales = |start_after: Option<u64>, limit: Option<u32>, token_id: Option<&str>| -> GetSalesResponse {
+        app.wrap()
+            .query_wasm_smart(&exchange, &QueryMsg::GetSales { start_after, limit, token_id: token_id.map(String::from) })
+            .unwrap()
+    };
+    let res = get_sales(None, Some(2), None);
+    assert_eq!(res.sales.iter().map(|s| s.token_id.as_str()).collect::<Vec<_>>(), vec!["2", "1"]);
+    assert_eq!(res.sales[0].buyer, "alice");
+    assert_eq!(res.sales[0].seller, "owner");
+    assert_eq!(res.sales[0].price, Uint128::new(1500000));
+    assert_eq!(res.sales[0].time, app.block_info().time.seconds());
+    let res = get_sales(Some(res.sales[1].id), None, None);
+    assert_eq!(res.sales.len(), 1);
+    assert_eq!(res.sales[0].token_id, "0");
+    let res = get_sales(None, None, Some("1"));
+    assert_eq!(res.sales.len(), 1);
+    assert_eq!(res.sales[0].price, Uint128::new(2000000));
+
+    let stats: GetStatsResponse = app.wrap().query_wasm_smart(&exchange, &QueryMsg::GetStats {}).unwrap();
+    assert_eq!(stats.sales, 3);
+    assert_eq!(stats.denoms.len(), 1);
+    assert_eq!(stats.denoms[0].denom, Denom::Native("inj".to_string()));
+    assert_eq!(stats.denoms[0].volume, Uint128::new(4500000));
+    assert_eq!(stats.denoms[0].last_price, Uint128::new(1500000));
+    assert_eq!(stats.denoms[0].all_time_high, Uint128::new(2000000));
+}
+
+#[test]
+fn floor_price() {
+    let (mut app, nft, exchange) = setup(vec![]);
+    let now = app.block_info().time.seconds();
+
+    let floor = |app: &App| -> Vec<(String, u128)> {
+        let res: FloorResponse = app.wrap().query_wasm_smart(&exchange, &QueryMsg::Floor {}).unwrap();
+        res.floors.into_iter().map(|f| (f.id, f.price.u128())).collect()
+    };
+    assert!(floor(&app).is_empty());
+
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    list_token(&mut app, &exchange, "0", "owner", 1800000);
+    mint_and_approve(&mut app, &nft, &exchange, "1", "owner");
+    list_token_until(&mut app, &exchange, "1", "owner", 1200000, now as i128 + 100);
+    mint_and_approve(&mut app, &nft, &exchange, "2", "owner");
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::ListDutch {
+            id: "2".to_string(),
+            price: Uint128::new(2000000),
+            dutch: DutchAuction {
+                end_price: Uint128::new(1000000),
+                start_time: now,
+                end_time: now + 1000,
+                decay: Decay::Linear,
+            },
+            expires: 0,
+            denom: None
+        },
+        &vec![],
+    ).expect("could not list");
+    assert_eq!(floor(&app), vec![("1".to_string(), 1200000)]);
+
+    // "1" has expired and the dutch listing has dropped below the fixed one
+    app.update_block(|block| block.time = block.time.plus_seconds(500));
+    assert_eq!(floor(&app), vec![("2".to_string(), 1500000)]);
+
+    let res: ListingResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::ListingByToken { id: "1".to_string() })
+        .unwrap();
+    assert!(res.expired);
+    assert_eq!(res.current_price, Uint128::new(1200000));
+    let res: ListingResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::ListingByToken { id: "2".to_string() })
+        .unwrap();
+    assert_eq!(res.listing.owner, "owner");
+    assert_eq!(res.current_price, Uint128::new(1500000));
+    assert!(app.wrap().query_wasm_smart::<ListingResponse>(&exchange, &QueryMsg::ListingByToken { id: "3".to_string() }).is_err());
+}
+
+#[test]
+fn stale_listing() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2000000)]);
+    for id in ["0", "1", "2"] {
+        mint_and_approve(&mut app, &nft, &exchange, id, "owner");
+        list_token(&mut app, &exchange, id, "owner", 1000000);
+    }
+
+    // "0" moves to another wallet and "1" loses its approval after being listed
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::TransferNft { recipient: "bob".to_string(), token_id: "0".to_string() },
+        &vec![],
+    ).expect("could not transfer");
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::Revoke { spender: exchange.to_string(), token_id: "1".to_string() },
+        &vec![],
+    ).expect("could not revoke");
+
+    for id in ["0", "1"] {
+        let err: ContractError = app.execute_contract(
+            Addr::unchecked("buyer"),
+            exchange.clone(),
+            &ExecuteMsg::Buy { id: id.to_string() },
+            &coins(1000000, "inj"),
+        ).unwrap_err().downcast().unwrap();
+        assert_eq!(err, ContractError::StaleListing {});
+    }
+    assert_eq!(app.wrap().query_balance("buyer", "inj").unwrap().amount, Uint128::new(2000000));
+
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("anyone"),
+        exchange.clone(),
+        &ExecuteMsg::InvalidateListing { id: "2".to_string() },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::ListingNotStale {});
+
+    // the new holder can relist without waiting for an invalidation
+    app.execute_contract(
+        Addr::unchecked("bob"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::Approve { token_id: "0".to_string(), spender: exchange.to_string(), expires: None },
+        &vec![],
+    ).expect("approval is borked");
+    list_token(&mut app, &exchange, "0", "bob", 2000000);
+    let res: ListingResponse = app.wrap().query_wasm_smart(&exchange, &QueryMsg::ListingByToken { id: "0".to_string() }).unwrap();
+    assert_eq!((res.listing.owner.as_str(), res.listing.price), ("bob", Uint128::new(2000000)));
+
+    app.execute_contract(
+        Addr::unchecked("anyone"),
+        exchange.clone(),
+        &ExecuteMsg::InvalidateListing { id: "1".to_string() },
+        &vec![],
+    ).expect("could not invalidate");
+    assert_eq!(listed_ids(&app, &exchange, true), vec!["2", "0"]);
+}
+
+/// cw721 approvals with an expiry, built here so the expiry is our own cw_utils type
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum NftApproveMsg {
+    Approve { spender: String, token_id: String, expires: Option<cw_utils::Expiration> },
+    ApproveAll { operator: String, expires: Option<cw_utils::Expiration> },
+}
+
+#[test]
+fn approvals() {
+    let (mut app, nft, exchange) = setup(vec![]);
+    let height = app.block_info().height;
+
+    for (id, owner) in [("0", "owner"), ("1", "owner"), ("2", "alice")] {
+        app.execute_contract(
+            Addr::unchecked("owner"),
+            nft.clone(),
+            &nft_multi_test::ExecuteMsg::Mint(nft_multi_test::MintMsg::<Extension> {
+                token_id: id.to_string(),
+                owner: owner.to_string(),
+                token_uri: Some("token_uri".to_string()),
+                extension: None
+            }),
+            &vec![]
+        ).expect("Minting is borked");
+    }
+    let approve = |app: &mut App, sender: &str, msg: NftApproveMsg| {
+        app.execute_contract(Addr::unchecked(sender), nft.clone(), &msg, &vec![]).expect("approval is borked");
+    };
+    let list = |app: &mut App, id: &str, owner: &str| {
+        app.execute_contract(
+            Addr::unchecked(owner),
+            exchange.clone(),
+            &ExecuteMsg::List { id: id.to_string(), price: Uint128::new(1000000), expires: 0, denom: None, reserved_for: None },
+            &vec![],
+        )
+    };
+
+    // approving someone else is not enough
+    approve(&mut app, "owner", NftApproveMsg::Approve { spender: "someone".to_string(), token_id: "0".to_string(), expires: None });
+    let err: ContractError = list(&mut app, "0", "owner").unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::NotApproved {});
+
+    // nor is an approval that has run out
+    approve(&mut app, "owner", NftApproveMsg::Approve {
+        spender: exchange.to_string(),
+        token_id: "1".to_string(),
+        expires: Some(cw_utils::Expiration::AtHeight(height + 1)),
+    });
+    app.update_block(|block| block.height += 1);
+    let err: ContractError = list(&mut app, "1", "owner").unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::NotApproved {});
+
+    // an operator approval covers every token of the owner
+    approve(&mut app, "alice", NftApproveMsg::ApproveAll { operator: "someone".to_string(), expires: None });
+    approve(&mut app, "alice", NftApproveMsg::ApproveAll { operator: exchange.to_string(), expires: None });
+    list(&mut app, "2", "alice").expect("could not list with an operator approval");
+}
+
+#[test]
+fn admin_roles() {
+    let (mut app, _nft, exchange) = setup(vec![]);
+    let height = app.block_info().height;
+    let exec = |app: &mut App, sender: &str, msg: ExecuteMsg| {
+        app.execute_contract(Addr::unchecked(sender), exchange.clone(), &msg, &vec![])
+    };
+    let exec_err = |app: &mut App, sender: &str, msg: ExecuteMsg| -> ContractError {
+        exec(app, sender, msg).unwrap_err().downcast().unwrap()
+    };
+    let roles = |app: &App| -> RolesResponse { app.wrap().query_wasm_smart(&exchange, &QueryMsg::Roles {}).unwrap() };
+
+    // the collection creator starts out as both admin and metadata editor
+    let res = roles(&app);
+    assert_eq!(res.admin, Some("owner".to_string()));
+    assert_eq!(res.metadata_editor, "owner");
+
+    assert_eq!(exec_err(&mut app, "anyone", ExecuteMsg::Flag { enabled: true, reason: None }), ContractError::Unauthorized {});
+    assert_eq!(exec_err(&mut app, "multisig", ExecuteMsg::AcceptAdmin {}), ContractError::NoPendingAdmin {});
+
+    // a proposal that runs out can't be accepted
+    exec(&mut app, "owner", ExecuteMsg::ProposeAdmin {
+        new_admin: "multisig".to_string(),
+        expiry: Some(cw_utils::Expiration::AtHeight(height + 1)),
+    }).expect("could not propose admin");
+    app.update_block(|block| block.height += 1);
+    assert_eq!(exec_err(&mut app, "multisig", ExecuteMsg::AcceptAdmin {}), ContractError::AdminProposalExpired {});
+
+    exec(&mut app, "owner", ExecuteMsg::ProposeAdmin { new_admin: "multisig".to_string(), expiry: None })
+        .expect("could not propose admin");
+    assert_eq!(roles(&app).pending_admin, Some("multisig".to_string()));
+    assert_eq!(exec_err(&mut app, "anyone", ExecuteMsg::AcceptAdmin {}), ContractError::Unauthorized {});
+    exec(&mut app, "multisig", ExecuteMsg::AcceptAdmin {}).expect("could not accept admin");
+    assert_eq!(roles(&app).admin, Some("multisig".to_string()));
+
+    // the old admin is out, the new one runs config and flagging
+    assert_eq!(exec_err(&mut app, "owner", ExecuteMsg::Flag { enabled: true, reason: None }), ContractError::Unauthorized {});
+    exec(&mut app, "multisig", ExecuteMsg::Flag { enabled: true, reason: None }).expect("could not flag");
+
+    // metadata is edited by its own role
+    let update_metadata = || ExecuteMsg::UpdateMetadata {
+        creators: None,
+        collection: None,
+        website: Some("https://example.com".to_string()),
+        contact: None,
+        twitter: None,
+        telegram: None,
+        discord: None,
+        description: None,
+        logo_uri: None,
+        banner_uri: None,
+        basis_points: None,
+    };
+    exec(&mut app, "owner", update_metadata()).expect("creator could not update metadata");
+    assert_eq!(exec_err(&mut app, "owner", ExecuteMsg::SetMetadataEditor { editor: "artist".to_string() }), ContractError::Unauthorized {});
+    exec(&mut app, "multisig", ExecuteMsg::SetMetadataEditor { editor: "artist".to_string() }).expect("could not set editor");
+    assert_eq!(exec_err(&mut app, "owner", update_metadata()), ContractError::Unauthorized {});
+    exec(&mut app, "artist", update_metadata()).expect("editor could not update metadata");
+
+    exec(&mut app, "multisig", ExecuteMsg::RenounceAdmin {}).expect("could not renounce");
+    assert_eq!(roles(&app).admin, None);
+    assert_eq!(exec_err(&mut app, "multisig", ExecuteMsg::Flag { enabled: false, reason: None }), ContractError::Unauthorized {});
+}
+
+#[test]
+fn flagged_collection() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2000000)]);
+    for id in ["0", "1", "2"] {
+        mint_and_approve(&mut app, &nft, &exchange, id, "owner");
+    }
+    list_token(&mut app, &exchange, "0", "owner", 1000000);
+    let now = app.block_info().time.seconds();
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::SendNft {
+            contract: exchange.to_string(),
+            token_id: "2".to_string(),
+            msg: to_binary(&ReceiveNftMsg::Auction {
+                reserve_price: Uint128::new(1000000),
+                min_increment: Uint128::new(100000),
+                end_time: now + 1000,
+            }).unwrap(),
+        },
+        &vec![],
+    ).expect("could not start auction");
+
+    let flag = |enabled: bool, reason: Option<&str>| ExecuteMsg::Flag { enabled, reason: reason.map(String::from) };
+    let err: ContractError = app.execute_contract(Addr::unchecked("moderator"), exchange.clone(), &flag(true, None), &vec![])
+        .unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Unauthorized {});
+    app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &ExecuteMsg::UpdateModerators {
+        add: vec!["moderator".to_string()],
+        remove: vec![],
+    }, &vec![]).expect("could not add moderator");
+    app.execute_contract(Addr::unchecked("moderator"), exchange.clone(), &flag(true, Some("stolen art")), &vec![])
+        .expect("could not flag");
+
+    let metadata: GetMetadataResponse = app.wrap().query_wasm_smart(&exchange, &QueryMsg::GetMetadata {}).unwrap();
+    assert!(metadata.flagged);
+    assert_eq!(metadata.flag_reason, Some("stolen art".to_string()));
+    assert_eq!(metadata.flag_time, Some(app.block_info().time.seconds()));
+
+    // no listing or buying while flagged
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::List { id: "1".to_string(), price: Uint128::new(1000000), expires: 0, denom: None, reserved_for: None },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Flagged {});
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1000000, "inj"),
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Flagged {});
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::PlaceBid { id: "2".to_string() },
+        &coins(1000000, "inj"),
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Flagged {});
+
+    // but sellers can still get out
+    app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &ExecuteMsg::DeList { id: "0".to_string() }, &vec![])
+        .expect("could not delist while flagged");
+    app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &ExecuteMsg::CancelAuction { id: "2".to_string() }, &vec![])
+        .expect("could not cancel auction while flagged");
+    assert_eq!(owner_of(&app, &nft, "2"), "owner");
+
+    app.execute_contract(Addr::unchecked("moderator"), exchange.clone(), &flag(false, Some("resolved")), &vec![])
+        .expect("could not unflag");
+    list_token(&mut app, &exchange, "1", "owner", 1000000);
+}
+
+/// An exchange's state exactly as 0.0.1 stored it, listings and percent creator shares included
+fn legacy_state(nft: &Addr, basis_points: u32, shares: (i8, i8)) -> String {
+    format!(
+        r#"{{"flagged":false,"collection":"collection","contract":"{}","description":"Test collection on Nebula","symbol":"TEST","logo_uri":"https://example.com/logo.png","banner_uri":"https://example.com/banner.png","supply":100,"owner":"owner","royalties":{{"seller_fee_basis_points":{},"creators":[{{"address":"creator","share":{}}},{{"address":"artist","share":{}}}]}},"listed":[{{"id":"0","owner":"owner","is_listed":true,"price":"1000000","expires":0}},{{"id":"1","owner":"owner","is_listed":true,"price":"3000000","expires":0}},{{"id":"2","owner":"owner","is_listed":false,"price":"0","expires":0}}],"contact":"","discord":"","twitter":"","telegram":"","website":""}}"#,
+        nft, basis_points, shares.0, shares.1,
+    )
+}
+
+#[test]
+fn migrate_legacy_listings() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 2000000)]);
+    let code_id = app.contract_data(&exchange).unwrap().code_id;
+    for id in ["0", "1"] {
+        mint_and_approve(&mut app, &nft, &exchange, id, "owner");
+    }
+
+    // rewind the exchange to 0.0.1, which had no roles or config of its own
+    let rewind = |app: &mut App, state: String| {
+        let mut storage = app.contract_storage_mut(&exchange);
+        for key in [&b"ownership"[..], b"metadata_editor", b"config"] {
+            storage.remove(key);
+        }
+        storage.set(b"state", state.as_bytes());
+        cw2::set_contract_version(storage.as_mut(), "Nebula Exchange", "0.0.1").unwrap();
+    };
+    let set_version = |app: &mut App, name: &str, version: &str| {
+        cw2::set_contract_version(app.contract_storage_mut(&exchange).as_mut(), name, version).unwrap();
+    };
+    let no_override = crate::msg::MigrateMsg { creators: None, basis_points: None };
+    let migrate_err = |app: &mut App| -> ContractError {
+        app.migrate_contract(Addr::unchecked("owner"), exchange.clone(), &no_override, code_id)
+            .unwrap_err().downcast().unwrap()
+    };
+
+    rewind(&mut app, legacy_state(&nft, 100, (60, 40)));
+    set_version(&mut app, "Some Other Contract", "0.0.1");
+    assert_eq!(migrate_err(&mut app), ContractError::WrongContract { name: "Some Other Contract".to_string() });
+    set_version(&mut app, "Nebula Exchange", "9.0.0");
+    assert_eq!(migrate_err(&mut app), ContractError::CannotDowngrade { from: "9.0.0".to_string(), to: "0.1.0".to_string() });
+
+    // shares that can't become basis points adding up to 10000 stop the migration
+    rewind(&mut app, legacy_state(&nft, 100, (110, -10)));
+    assert_eq!(migrate_err(&mut app), ContractError::InvalidCreatorShares {});
+    rewind(&mut app, legacy_state(&nft, 100, (60, 30)));
+    assert_eq!(migrate_err(&mut app), ContractError::InvalidCreatorShares {});
+
+    // legacy royalties are checked like new ones
+    rewind(&mut app, legacy_state(&nft, 9500, (60, 40)));
+    assert_eq!(migrate_err(&mut app), ContractError::RoyaltyTooHigh { max: 9000 });
+    rewind(&mut app, legacy_state(&nft, 100, (60, 40)).replace("\"artist\"", "\"Artist\""));
+    assert!(matches!(migrate_err(&mut app), ContractError::Std(_)));
+
+    // a royalty above the default cap raises the cap instead of failing
+    rewind(&mut app, legacy_state(&nft, 1500, (60, 40)));
+    let res = app.migrate_contract(Addr::unchecked("owner"), exchange.clone(), &no_override, code_id)
+        .expect("could not migrate");
+    assert_eq!(cw2::get_contract_version(app.contract_storage(&exchange).as_ref()).unwrap().version, "0.1.0");
+    let attrs = exchange_attributes(&res, &exchange);
+    assert!(attrs.contains(&("migrated_listings".to_string(), "2".to_string())));
+    assert!(attrs.contains(&("token_ids".to_string(), "0,1".to_string())));
+    assert!(attrs.contains(&("remaining_listings".to_string(), "0".to_string())));
+    assert_eq!(attrs.iter().filter(|(k, _)| k == "price").count(), 2);
+    let config: crate::state::Config = app.wrap().query_wasm_smart(&exchange, &QueryMsg::Config {}).unwrap();
+    assert_eq!(config.max_royalty_basis_points, 1500);
+
+    // both listings made it over and trade like new ones
+    let res: GetListedResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::GetListed {
+            start_after: None,
+            limit: None,
+            order: None,
+            min_price: None,
+            max_price: None,
+            owner: None,
+            include_expired: None,
+        })
+        .unwrap();
+    assert_eq!(res.number, 2);
+    assert_eq!(res.listed.iter().map(|t| t.id.as_str()).collect::<Vec<_>>(), vec!["0", "1"]);
+    assert_eq!(res.listed[0].denom, Denom::Native("inj".to_string()));
+
+    // percent shares are now basis points
+    let res: SimulateBuyResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::SimulateBuy { id: "0".to_string() })
+        .unwrap();
+    assert_eq!(res.royalties, vec![
+        Payment { address: "creator".to_string(), amount: Uint128::new(90000) },
+        Payment { address: "artist".to_string(), amount: Uint128::new(60000) },
+    ]);
+    app.execute_contract(
+        Addr::unchecked("buyer"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1000000, "inj"),
+    ).expect("could not buy a migrated listing");
+    assert_eq!(owner_of(&app, &nft, "0"), "buyer");
+    assert_eq!(app.wrap().query_balance("artist", "inj").unwrap().amount, Uint128::new(60000));
+
+    // the creator can still edit the metadata without touching the royalties
+    app.execute_contract(Addr::unchecked("owner"), exchange.clone(), &ExecuteMsg::UpdateMetadata {
+        creators: None,
+        collection: None,
+        website: None,
+        contact: None,
+        twitter: Some("https://twitter.com/nebula".to_string()),
+        telegram: None,
+        discord: None,
+        description: None,
+        logo_uri: None,
+        banner_uri: None,
+        basis_points: None,
+    }, &vec![]).expect("could not update metadata after migrating");
+    let metadata: GetMetadataResponse = app.wrap().query_wasm_smart(&exchange, &QueryMsg::GetMetadata {}).unwrap();
+    assert_eq!(metadata.twitter, "https://twitter.com/nebula");
+
+    // running it again is harmless
+    app.migrate_contract(Addr::unchecked("owner"), exchange.clone(), &no_override, code_id)
+        .expect("could not migrate twice");
+    assert_eq!(listed_ids(&app, &exchange, true), vec!["1"]);
+}
+
+#[test]
+fn migrate_legacy_listings_in_batches() {
+    let (mut app, nft, exchange) = setup(vec![]);
+    let code_id = app.contract_data(&exchange).unwrap().code_id;
+
+    // 103 more listings ahead of the usual two, more than one migration can move
+    let extra = (10..113)
+        .map(|id| format!(r#"{{"id":"{}","owner":"owner","is_listed":true,"price":"2000000","expires":0}}"#, id))
+        .collect::<Vec<String>>()
+        .join(",");
+    let state = legacy_state(&nft, 100, (60, 40)).replace(r#""listed":["#, &format!(r#""listed":[{},"#, extra));
+    {
+        let mut storage = app.contract_storage_mut(&exchange);
+        for key in [&b"ownership"[..], b"metadata_editor", b"config"] {
+            storage.remove(key);
+        }
+        storage.set(b"state", state.as_bytes());
+        cw2::set_contract_version(storage.as_mut(), "Nebula Exchange", "0.0.1").unwrap();
+    }
+
+    let res = app.migrate_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &crate::msg::MigrateMsg { creators: None, basis_points: None },
+        code_id,
+    ).expect("could not migrate");
+    let attrs = exchange_attributes(&res, &exchange);
+    assert!(attrs.contains(&("migrated_listings".to_string(), "100".to_string())));
+    assert!(attrs.contains(&("remaining_listings".to_string(), "5".to_string())));
+
+    // anyone can move the rest, in batches of their choosing
+    let mut migrate_listings = |limit: Option<u32>| -> Vec<(String, String)> {
+        let res = app.execute_contract(
+            Addr::unchecked("anyone"),
+            exchange.clone(),
+            &ExecuteMsg::MigrateListings { limit },
+            &vec![],
+        ).expect("could not migrate listings");
+        exchange_attributes(&res, &exchange)
+    };
+    let attrs = migrate_listings(Some(3));
+    assert!(attrs.contains(&("token_ids".to_string(), "110,111,112".to_string())));
+    assert!(attrs.contains(&("remaining_listings".to_string(), "2".to_string())));
+    let attrs = migrate_listings(None);
+    assert!(attrs.contains(&("token_ids".to_string(), "0,1".to_string())));
+    assert!(attrs.contains(&("remaining_listings".to_string(), "0".to_string())));
+    let attrs = migrate_listings(None);
+    assert!(attrs.contains(&("migrated_listings".to_string(), "0".to_string())));
+
+    let res: GetListedResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::GetListed {
+            start_after: None,
+            limit: Some(1),
+            order: None,
+            min_price: None,
+            max_price: None,
+            owner: None,
+            include_expired: None,
+        })
+        .unwrap();
+    assert_eq!(res.number, 105);
+}
+
+#[test]
+fn migrate_royalty_override() {
+    let (mut app, nft, exchange) = setup(vec![]);
+    let code_id = app.contract_data(&exchange).unwrap().code_id;
+    mint_and_approve(&mut app, &nft, &exchange, "0", "owner");
+    {
+        let mut storage = app.contract_storage_mut(&exchange);
+        for key in [&b"ownership"[..], b"metadata_editor", b"config"] {
+            storage.remove(key);
+        }
+        storage.set(b"state", legacy_state(&nft, 100, (60, 30)).as_bytes());
+        cw2::set_contract_version(storage.as_mut(), "Nebula Exchange", "0.0.1").unwrap();
+    }
+
+    // royalties passed to migrate replace broken legacy ones, and are checked the same way
+    let migrate_with = |app: &mut App, creators: Vec<(&str, u16)>, basis_points: u16| {
+        let msg = crate::msg::MigrateMsg {
+            creators: Some(creators.into_iter().map(|(address, share)| Creator { address: address.to_string(), share }).collect()),
+            basis_points: Some(basis_points),
+        };
+        app.migrate_contract(Addr::unchecked("owner"), exchange.clone(), &msg, code_id)
+    };
+    let err: ContractError = migrate_with(&mut app, vec![("creator", 5000)], 200).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::InvalidCreatorShares {});
+    migrate_with(&mut app, vec![("creator", 7500), ("artist", 2500)], 200).expect("could not migrate with new royalties");
+
+    let res: SimulateBuyResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::SimulateBuy { id: "0".to_string() })
+        .unwrap();
+    assert_eq!(res.royalties, vec![
+        Payment { address: "creator".to_string(), amount: Uint128::new(15000) },
+        Payment { address: "artist".to_string(), amount: Uint128::new(5000) },
+    ]);
+}
+
+#[test]
+fn batch_listing() {
+    let (mut app, nft, exchange) = setup(vec![]);
+    for id in ["0", "1", "2", "3"] {
+        mint_and_approve(&mut app, &nft, &exchange, id, "owner");
+    }
+    let item = |id: &str, price: u128| ListItem { id: id.to_string(), price: Uint128::new(price), expires: 0, denom: None, reserved_for: None };
+
+    // one bad item sinks the whole batch
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::TransferNft { recipient: "bob".to_string(), token_id: "3".to_string() },
+        &vec![],
+    ).expect("could not transfer");
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::BatchList { items: vec![item("0", 1000000), item("3", 1000000)] },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Unauthorized {});
+    assert!(listed_ids(&app, &exchange, true).is_empty());
+
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::BatchList { items: (0..101).map(|i| item(&i.to_string(), 1000000)).collect() },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::BatchTooLarge { max: 100 });
+
+    let res = app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::BatchList { items: vec![item("0", 3000000), item("1", 1000000), item("2", 2000000)] },
+        &vec![],
+    ).expect("could not batch list");
+    let attrs = exchange_attributes(&res, &exchange);
+    assert_eq!(attrs[..2], [("action".to_string(), "batch_list".to_string()), ("count".to_string(), "3".to_string())]);
+    assert_eq!(attrs.iter().filter(|(k, _)| k == "token_id").count(), 3);
+    assert_eq!(listed_ids(&app, &exchange, false), vec!["1", "2", "0"]);
+
+    // a price update moves the listing in the order book
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("bob"),
+        exchange.clone(),
+        &ExecuteMsg::UpdatePrice { id: "0".to_string(), price: Uint128::new(500000), expires: 0 },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Unauthorized {});
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::UpdatePrice { id: "0".to_string(), price: Uint128::new(500000), expires: 0 },
+        &vec![],
+    ).expect("could not update price");
+    assert_eq!(listed_ids(&app, &exchange, false), vec!["0", "1", "2"]);
+    let res: FloorResponse = app.wrap().query_wasm_smart(&exchange, &QueryMsg::Floor {}).unwrap();
+    assert_eq!(res.floors[0].price, Uint128::new(500000));
+
+    // expired and stale listings can't be revived by a price update
+    let now = app.block_info().time.seconds() as i128;
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::UpdatePrice { id: "2".to_string(), price: Uint128::new(2000000), expires: now + 10 },
+        &vec![],
+    ).expect("could not update expiry");
+    app.update_block(|block| block.time = block.time.plus_seconds(20));
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::UpdatePrice { id: "2".to_string(), price: Uint128::new(2000000), expires: 0 },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::ListingExpired {});
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::TransferNft { recipient: "bob".to_string(), token_id: "1".to_string() },
+        &vec![],
+    ).expect("could not transfer");
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::UpdatePrice { id: "1".to_string(), price: Uint128::new(2000000), expires: 0 },
+        &vec![],
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::StaleListing {});
+
+    app.execute_contract(
+        Addr::unchecked("owner"),
+        exchange.clone(),
+        &ExecuteMsg::BatchDeList { ids: vec!["0".to_string(), "2".to_string()] },
+        &vec![],
+    ).expect("could not batch delist");
+    assert_eq!(listed_ids(&app, &exchange, true), vec!["1"]);
+}
+
+#[test]
+fn sweep() {
+    let (mut app, nft, exchange) = setup(vec![("buyer", 20000000)]);
+    for (id, seller, price) in [("0", "alice", 1000000), ("1", "alice", 2000000), ("2", "bob", 3000000), ("3", "alice", 4000000), ("4", "buyer", 100)] {
+        mint_and_approve(&mut app, &nft, &exchange, id, seller);
+        list_token(&mut app, &exchange, id, seller, price);
+    }
+    let fee_wallet = app
+        .wrap()
+        .query_wasm_smart::<SimulateBuyResponse>(&exchange, &QueryMsg::SimulateBuy { id: "0".to_string() })
+        .unwrap()
+        .fees[0]
+        .address
+        .clone();
+    let sweep = |max_count: u32, max_total_price: u128, ids: Option<Vec<&str>>| ExecuteMsg::Sweep {
+        max_count,
+        max_total_price: Uint128::new(max_total_price),
+        ids: ids.map(|ids| ids.into_iter().map(String::from).collect()),
+    };
+
+    let err: ContractError = app
+        .execute_contract(Addr::unchecked("buyer"), exchange.clone(), &sweep(101, 1000000, None), &coins(1000000, "inj"))
+        .unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::BatchTooLarge { max: 100 });
+
+    // the three cheapest are 0, 1 and 2 (the buyer's own listing is skipped), but 2 is over budget
+    let res = app
+        .execute_contract(Addr::unchecked("buyer"), exchange.clone(), &sweep(3, 5000000, None), &coins(6000000, "inj"))
+        .expect("could not sweep");
+    let attrs = exchange_attributes(&res, &exchange);
+    assert_eq!(attrs[..6], [
+        ("action".to_string(), "sweep".to_string()),
+        ("buyer".to_string(), "buyer".to_string()),
+        ("denom".to_string(), "inj".to_string()),
+        ("count".to_string(), "2".to_string()),
+        ("total".to_string(), "3000000".to_string()),
+        ("refund".to_string(), "3000000".to_string()),
+    ]);
+    // alice is paid once for both tokens, alongside the creator, the fee wallet and the refund
+    assert_eq!(res.events.iter().filter(|e| e.ty == "transfer").count(), 4);
+    assert_eq!(owner_of(&app, &nft, "0"), "buyer");
+    assert_eq!(owner_of(&app, &nft, "1"), "buyer");
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(2880000));
+    assert_eq!(app.wrap().query_balance("creator", "inj").unwrap().amount, Uint128::new(30000));
+    assert_eq!(app.wrap().query_balance(&fee_wallet, "inj").unwrap().amount, Uint128::new(90000));
+    assert_eq!(app.wrap().query_balance("buyer", "inj").unwrap().amount, Uint128::new(17000000));
+    assert_eq!(listed_ids(&app, &exchange, true), vec!["4", "2", "3"]);
+
+    // sweeping chosen ids, duplicates counted once
+    let res = app
+        .execute_contract(Addr::unchecked("buyer"), exchange.clone(), &sweep(5, 10000000, Some(vec!["3", "2", "3"])), &coins(7000000, "inj"))
+        .expect("could not sweep ids");
+    let attrs = exchange_attributes(&res, &exchange);
+    assert!(attrs.contains(&("count".to_string(), "2".to_string())));
+    assert!(attrs.contains(&("refund".to_string(), "0".to_string())));
+    assert_eq!(owner_of(&app, &nft, "2"), "buyer");
+    assert_eq!(owner_of(&app, &nft, "3"), "buyer");
+    assert_eq!(app.wrap().query_balance("bob", "inj").unwrap().amount, Uint128::new(2880000));
+
+    let err: ContractError = app
+        .execute_contract(Addr::unchecked("buyer"), exchange.clone(), &sweep(5, 1000000, None), &coins(1000000, "inj"))
+        .unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::NothingToSweep {});
+    assert_eq!(app.wrap().query_balance("buyer", "inj").unwrap().amount, Uint128::new(10000000));
+}
+
+#[test]
+fn reserved_listing() {
+    let (mut app, nft, exchange) = setup(vec![("bob", 2000000), ("carol", 4000000)]);
+    mint_and_approve(&mut app, &nft, &exchange, "0", "alice");
+    mint_and_approve(&mut app, &nft, &exchange, "1", "alice");
+    let res = app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &ExecuteMsg::List { id: "0".to_string(), price: Uint128::new(1000000), expires: 0, denom: None, reserved_for: Some("bob".to_string()) },
+        &vec![],
+    ).expect("could not list");
+    assert!(exchange_attributes(&res, &exchange).contains(&("reserved_for".to_string(), "bob".to_string())));
+    list_token(&mut app, &exchange, "1", "alice", 2000000);
+
+    // listed publicly, but marked and left out of the floor
+    let res: GetListedResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::GetListed {
+            start_after: None,
+            limit: None,
+            order: None,
+            min_price: None,
+            max_price: None,
+            owner: None,
+            include_expired: None,
+        })
+        .unwrap();
+    assert_eq!(res.listed[0].reserved_for, Some("bob".to_string()));
+    assert_eq!(res.listed[1].reserved_for, None);
+    let res: FloorResponse = app.wrap().query_wasm_smart(&exchange, &QueryMsg::Floor {}).unwrap();
+    assert_eq!(res.floors[0].id, "1");
+
+    let err: ContractError = app.execute_contract(
+        Addr::unchecked("carol"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1000000, "inj"),
+    ).unwrap_err().downcast().unwrap();
+    assert_eq!(err, ContractError::Reserved {});
+
+    // a sweep passes over it
+    app.execute_contract(
+        Addr::unchecked("carol"),
+        exchange.clone(),
+        &ExecuteMsg::Sweep { max_count: 2, max_total_price: Uint128::new(4000000), ids: None },
+        &coins(4000000, "inj"),
+    ).expect("could not sweep");
+    assert_eq!(owner_of(&app, &nft, "1"), "carol");
+    assert_eq!(listed_ids(&app, &exchange, true), vec!["0"]);
+    let res: FloorResponse = app.wrap().query_wasm_smart(&exchange, &QueryMsg::Floor {}).unwrap();
+    assert!(res.floors.is_empty());
+
+    app.execute_contract(
+        Addr::unchecked("bob"),
+        exchange.clone(),
+        &ExecuteMsg::Buy { id: "0".to_string() },
+        &coins(1000000, "inj"),
+    ).expect("could not buy a listing reserved for the buyer");
+    assert_eq!(owner_of(&app, &nft, "0"), "bob");
+}
+
+#[test]
+fn swaps() {
+    let (mut app, nft, exchange) = setup(vec![("alice", 5000000)]);
+    let other_code_id = app.store_code(cw721_contract());
+    let other = nft_multi_test::instantiate(&mut app, other_code_id, &nft_multi_test::InstantiateMsg {
+        name: "Other Collection".to_string(),
+        symbol: "OTHER".to_string(),
+        minter: "owner".to_string(),
+    }).expect("Could not instantiate nft contract");
+    let other = Addr::unchecked(other);
+    mint_and_approve(&mut app, &other, &exchange, "a1", "alice");
+    mint_and_approve(&mut app, &nft, &exchange, "0", "alice");
+    mint_and_approve(&mut app, &nft, &exchange, "1", "bob");
+    mint_and_approve(&mut app, &nft, &exchange, "2", "bob");
+    list_token(&mut app, &exchange, "1", "bob", 3000000);
+
+    let item = |contract: &Addr, id: &str| SwapItem { contract: contract.to_string(), token_id: id.to_string() };
+    let propose = |offered: Vec<SwapItem>, requested: Vec<&str>| ExecuteMsg::ProposeSwap {
+        offered,
+        requested: requested.into_iter().map(String::from).collect(),
+        expires: 0,
+    };
+    let swap_err = |app: &mut App, sender: &str, msg: &ExecuteMsg, funds: &[cosmwasm_std::Coin]| -> ContractError {
+        app.execute_contract(Addr::unchecked(sender), exchange.clone(), msg, funds)
+            .unwrap_err().downcast().unwrap()
+    };
+
+    let err = swap_err(&mut app, "alice", &propose(vec![item(&nft, "1")], vec!["2"]), &[]);
+    assert_eq!(err, ContractError::Unauthorized {});
+    let err = swap_err(&mut app, "alice", &propose(vec![item(&nft, "0")], vec!["0"]), &[]);
+    assert_eq!(err, ContractError::InvalidSwap {});
+    let err = swap_err(&mut app, "alice", &propose(vec![], vec!["1"]), &[]);
+    assert_eq!(err, ContractError::InvalidSwap {});
+
+    app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &propose(vec![item(&other, "a1"), item(&nft, "0")], vec!["1", "2"]),
+        &coins(1000000, "inj"),
+    ).expect("could not propose swap");
+    let res: SwapsResponse = app
+        .wrap()
+        .query_wasm_smart(&exchange, &QueryMsg::Swaps { proposer: Some("alice".to_string()), start_after: None, limit: None })
+        .unwrap();
+    assert_eq!(res.swaps.len(), 1);
+    let id = res.swaps[0].id;
+
+    // only the holder of every requested token can accept, and only the proposer can cancel
+    let err = swap_err(&mut app, "carol", &ExecuteMsg::AcceptSwap { id }, &[]);
+    assert_eq!(err, ContractError::Unauthorized {});
+    let err = swap_err(&mut app, "bob", &ExecuteMsg::CancelSwap { id }, &[]);
+    assert_eq!(err, ContractError::Unauthorized {});
+
+    app.execute_contract(Addr::unchecked("bob"), exchange.clone(), &ExecuteMsg::AcceptSwap { id }, &vec![])
+        .expect("could not accept swap");
+    assert_eq!(owner_of(&app, &other, "a1"), "bob");
+    assert_eq!(owner_of(&app, &nft, "0"), "bob");
+    assert_eq!(owner_of(&app, &nft, "1"), "alice");
+    assert_eq!(owner_of(&app, &nft, "2"), "alice");
+    assert!(listed_ids(&app, &exchange, true).is_empty());
+    // the cash leg pays royalties and the fee like a sale
+    assert_eq!(app.wrap().query_balance("creator", "inj").unwrap().amount, Uint128::new(10000));
+    assert_eq!(app.wrap().query_balance("bob", "inj").unwrap().amount, Uint128::new(960000));
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(4000000));
+    assert!(app.wrap().query_wasm_smart::<crate::state::Swap>(&exchange, &QueryMsg::Swap { id }).is_err());
+
+    // a swap goes stale once the proposer's token moves, and cancelling refunds the funds
+    app.execute_contract(
+        Addr::unchecked("alice"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::Approve { token_id: "1".to_string(), spender: exchange.to_string(), expires: None },
+        &vec![],
+    ).expect("approval is borked");
+    app.execute_contract(
+        Addr::unchecked("alice"),
+        exchange.clone(),
+        &propose(vec![item(&nft, "1")], vec!["0"]),
+        &coins(500000, "inj"),
+    ).expect("could not propose swap");
+    let id = id + 1;
+    app.execute_contract(
+        Addr::unchecked("alice"),
+        nft.clone(),
+        &nft_multi_test::ExecuteMsg::<Option<Empty>>::TransferNft { recipient: "carol".to_string(), token_id: "1".to_string() },
+        &vec![],
+    ).expect("could not transfer");
+    let err = swap_err(&mut app, "bob", &ExecuteMsg::AcceptSwap { id }, &[]);
+    assert_eq!(err, ContractError::StaleSwap {});
+    app.execute_contract(Addr::unchecked("alice"), exchange.clone(), &ExecuteMsg::CancelSwap { id }, &vec![])
+        .expect("could not cancel swap");
+    assert_eq!(app.wrap().query_balance("alice", "inj").unwrap().amount, Uint128::new(4000000));
+}